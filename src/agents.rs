@@ -1,4 +1,2 @@
 pub mod player;
 pub mod ant;
-
-pub const SPEED: i32 = 1;
\ No newline at end of file
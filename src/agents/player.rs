@@ -1,26 +1,52 @@
 use crate::plugins::{
+    aseprite::AsepriteAsset,
+    clock::{GameClock, SIMULATION},
+    config::{ConfigChanged, GameConfig},
+    depth::{Depth, RenderLayer},
     items::Owner,
-    world::{
-        AddItemToWorldEvent, GameWorld, OldPosition, Position, RemoveItemFromWorldEvent,
-        YZ_PROJECTION_RATIO, TextureOffset,
-    },
+    world::{GameWorld, OldPosition, Position, TextureOffset, WorldItemEvent},
 };
 
-use super::SPEED;
 use benimator::{AnimationPlugin, Play, SpriteSheetAnimation, SpriteSheetAnimationState};
-use bevy::prelude::*;
+use bevy::{input::InputSystem, prelude::*};
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+// Resource
+// 1 tick 分のプレイヤーへの指示. キーボード (またはリプレイ) から埋める
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputCommands {
+    // 各成分 -1, 0, 1
+    pub walk: (i32, i32),
+    pub interact: bool,
+    // 押した瞬間だけ true. 古いリプレイには無い
+    #[serde(default)]
+    pub undo: bool,
+    #[serde(default)]
+    pub redo: bool,
+}
+
+const UNDO_KEY: KeyCode = KeyCode::U;
+const REDO_KEY: KeyCode = KeyCode::Y;
+
+// Resource
+// tick の無いフレームで押されても取りこぼさないよう, 次の tick まで押下を持っておく
 #[derive(Default)]
-struct InputCommands {}
+struct LatchedKeys {
+    undo: bool,
+    redo: bool,
+}
 
 #[derive(Default, Clone)]
 struct AnimationHandles {
+    sprite: Handle<AsepriteAsset>,
     idle: Handle<SpriteSheetAnimation>,
     walk: Handle<SpriteSheetAnimation>,
     pick: Handle<SpriteSheetAnimation>,
 }
 
+const PLAYER_SPRITE: &str = "sprites/player.aseprite";
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum PlayerState {
     Idle,
@@ -35,7 +61,26 @@ pub enum PlayerState {
 //     pub right_hand: Option<Entity>,
 // }
 
-pub struct RightHand(Option<Entity>);
+pub struct RightHand(pub Option<Entity>);
+
+#[derive(Clone, Debug)]
+pub struct Vitals {
+    pub health: f32,
+    pub max_health: f32,
+    pub hunger: f32,
+    pub max_hunger: f32,
+}
+
+impl Default for Vitals {
+    fn default() -> Self {
+        Vitals {
+            health: 100.0,
+            max_health: 100.0,
+            hunger: 100.0,
+            max_hunger: 100.0,
+        }
+    }
+}
 
 #[derive(Bundle)]
 pub struct PlayerBundle {
@@ -44,32 +89,57 @@ pub struct PlayerBundle {
     pub pos: Position,
     pub old_pos: OldPosition,
     pub offset: TextureOffset,
+    pub vitals: Vitals,
+    pub depth: Depth,
     pub action_timer: Timer,
 }
 
 pub struct PlayerPlugin;
 
-const PLAYER_LAYER: f32 = 10.0;
-const PLAYER_Y_OFFSET: f32 = 28.0;
-
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<AnimationHandles>()
+            .init_resource::<InputCommands>()
+            .init_resource::<LatchedKeys>()
             .add_startup_system(setup.system())
             .add_plugin(AnimationPlugin)
-            .add_system(end_action_process.system().label("end_action_process"))
-            .add_system(input.system().label("input").after("end_action_process"))
-            .add_system(start_action_process.system().after("input"));
+            .add_system(apply_sprite.system())
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                latch_keys.system().label("latch_keys").after(InputSystem),
+            )
+            .add_system(apply_config.system().after("config"))
+            // SIMULATION は Playing の間だけ回る
+            .add_system_set_to_stage(
+                SIMULATION,
+                SystemSet::new()
+                    .with_system(read_input.system().label("read_input"))
+                    .with_system(end_action_process.system().label("end_action_process"))
+                    .with_system(
+                        input
+                            .system()
+                            .label("input")
+                            .after("read_input")
+                            .after("end_action_process"),
+                    )
+                    .with_system(
+                        start_action_process
+                            .system()
+                            .label("start_action_process")
+                            .after("input"),
+                    ),
+            );
     }
 }
 
 fn setup(
     mut commands: Commands,
+    config: Res<GameConfig>,
     mut animation_handles: ResMut<AnimationHandles>,
     asset_server: Res<AssetServer>,
-    mut textures: ResMut<Assets<TextureAtlas>>,
     mut animations: ResMut<Assets<SpriteSheetAnimation>>,
 ) {
+    // aseprite にタグが無い場合のフレーム範囲
     animation_handles.idle = animations.add(SpriteSheetAnimation::from_range(
         0..=1,
         Duration::from_millis(200),
@@ -83,24 +153,24 @@ fn setup(
         Duration::from_millis(100),
     ));
 
-    let texture: Handle<Texture> = asset_server.get_handle("sprites/player.png");
+    animation_handles.sprite = asset_server.load(PLAYER_SPRITE);
     let player_bundle = PlayerBundle {
         state: PlayerState::Idle,
         right_hand: RightHand(None),
         pos: Position { x: 0, y: 0 },
         old_pos: OldPosition { x: 0, y: 0 },
-        offset: TextureOffset { x: 0.0, y: PLAYER_Y_OFFSET },
+        offset: TextureOffset {
+            x: 0.0,
+            y: config.world.player_y_offset,
+        },
+        vitals: Vitals::default(),
+        depth: Depth::new(RenderLayer::Agent),
         action_timer: Timer::new(Default::default(), false),
     };
 
     commands
         .spawn_bundle(SpriteSheetBundle {
-            texture_atlas: textures.add(TextureAtlas::from_grid(
-                texture,
-                Vec2::new(32.0, 64.0),
-                11,
-                1,
-            )),
+            texture_atlas: asset_server.get_handle(format!("{}#atlas", PLAYER_SPRITE).as_str()),
             transform: Transform {
                 translation: Vec3::new(0.0, 0.0, 0.0),
                 scale: Vec3::new(1.0, 1.0, 0.0),
@@ -113,16 +183,74 @@ fn setup(
         .insert_bundle(player_bundle);
 }
 
+fn apply_sprite(
+    mut ev_asset: EventReader<AssetEvent<AsepriteAsset>>,
+    mut animation_handles: ResMut<AnimationHandles>,
+    sprites: Res<Assets<AsepriteAsset>>,
+    mut query: Query<(
+        &PlayerState,
+        &mut Handle<TextureAtlas>,
+        &mut Handle<SpriteSheetAnimation>,
+    )>,
+) {
+    for ev in ev_asset.iter() {
+        let handle = match ev {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != animation_handles.sprite {
+            continue;
+        }
+        let sprite = match sprites.get(handle) {
+            Some(sprite) => sprite,
+            None => continue,
+        };
+        if let Some(idle) = sprite.animation("idle") {
+            animation_handles.idle = idle;
+        }
+        if let Some(walk) = sprite.animation("walk") {
+            animation_handles.walk = walk;
+        }
+        if let Some(pick) = sprite.animation("pick") {
+            animation_handles.pick = pick;
+        }
+        query
+            .iter_mut()
+            .for_each(|(state, mut atlas, mut animation)| {
+                *atlas = sprite.atlas.clone();
+                *animation = match *state {
+                    PlayerState::Idle => animation_handles.idle.clone(),
+                    PlayerState::Walk(_) => animation_handles.walk.clone(),
+                    _ => animation_handles.pick.clone(),
+                };
+            });
+    }
+}
+
+fn apply_config(
+    mut events: EventReader<ConfigChanged>,
+    config: Res<GameConfig>,
+    mut query: Query<(&mut TextureOffset,), (With<PlayerState>,)>,
+) {
+    for ev in events.iter() {
+        if ev.contains("world.player_y_offset") {
+            query.iter_mut().for_each(|(mut offset,)| {
+                offset.y = config.world.player_y_offset;
+            });
+        }
+    }
+}
+
 fn end_action_process(
     mut commands: Commands,
-    mut add_event_writer: EventWriter<AddItemToWorldEvent>,
-    time: Res<Time>,
+    mut item_event_writer: EventWriter<WorldItemEvent>,
+    clock: Res<GameClock>,
     mut query: Query<(&mut PlayerState, &mut RightHand, &mut Timer, &Position, &mut OldPosition)>,
 ) {
     query
         .iter_mut()
         .for_each(|(mut state, mut right_hand, mut timer, pos, mut old_pos)| {
-            timer.tick(time.delta());
+            timer.tick(clock.tick_duration());
             if !timer.finished() {
                 return;
             }
@@ -137,7 +265,7 @@ fn end_action_process(
                 PlayerState::Drop => {
                     if let Some(item_entity) = right_hand.0 {
                         commands.entity(item_entity).remove::<Owner>().insert(*pos);
-                        add_event_writer.send(AddItemToWorldEvent(item_entity, *pos));
+                        item_event_writer.send(WorldItemEvent::Add(item_entity, *pos));
                         right_hand.0 = None;
                     }
                 }
@@ -147,7 +275,7 @@ fn end_action_process(
                             .entity(item_entity_hand)
                             .remove::<Owner>()
                             .insert(*pos);
-                        add_event_writer.send(AddItemToWorldEvent(item_entity_hand, *pos));
+                        item_event_writer.send(WorldItemEvent::Add(item_entity_hand, *pos));
                         right_hand.0 = Some(item_entity_ground);
                     }
                 }
@@ -156,8 +284,40 @@ fn end_action_process(
         });
 }
 
-fn input(
+fn latch_keys(key_input: Res<Input<KeyCode>>, mut latched: ResMut<LatchedKeys>) {
+    latched.undo |= key_input.just_pressed(UNDO_KEY);
+    latched.redo |= key_input.just_pressed(REDO_KEY);
+}
+
+fn read_input(
     key_input: Res<Input<KeyCode>>,
+    mut latched: ResMut<LatchedKeys>,
+    mut input_commands: ResMut<InputCommands>,
+) {
+    let mut walk = (0, 0);
+    if key_input.pressed(KeyCode::Left) {
+        walk.0 -= 1;
+    }
+    if key_input.pressed(KeyCode::Right) {
+        walk.0 += 1;
+    }
+    if key_input.pressed(KeyCode::Down) {
+        walk.1 -= 1;
+    }
+    if key_input.pressed(KeyCode::Up) {
+        walk.1 += 1;
+    }
+    *input_commands = InputCommands {
+        walk,
+        interact: key_input.pressed(KeyCode::Z),
+        undo: std::mem::take(&mut latched.undo),
+        redo: std::mem::take(&mut latched.redo),
+    };
+}
+
+fn input(
+    input_commands: Res<InputCommands>,
+    config: Res<GameConfig>,
     mut query: Query<(&mut PlayerState, &mut RightHand, &Timer, &Position)>,
     world_query: Query<(&GameWorld,)>,
 ) {
@@ -165,7 +325,7 @@ fn input(
         if !timer.finished() {
             return;
         }
-        if key_input.pressed(KeyCode::Z) {
+        if input_commands.interact {
             for (world,) in world_query.iter() {
                 if let Some(item_entity_ground) = world.item_map.get(&pos) {
                     if let Some(_) = right_hand.0 {
@@ -181,19 +341,8 @@ fn input(
             }
         }
         if *state == PlayerState::Idle {
-            let mut walk = (0, 0);
-            if key_input.pressed(KeyCode::Left) {
-                walk.0 -= SPEED;
-            }
-            if key_input.pressed(KeyCode::Right) {
-                walk.0 += SPEED;
-            }
-            if key_input.pressed(KeyCode::Down) {
-                walk.1 -= SPEED;
-            }
-            if key_input.pressed(KeyCode::Up) {
-                walk.1 += SPEED;
-            }
+            let speed = config.movement.speed;
+            let walk = (input_commands.walk.0 * speed, input_commands.walk.1 * speed);
             if walk != (0, 0) {
                 *state = PlayerState::Walk(walk);
             }
@@ -203,7 +352,8 @@ fn input(
 
 fn start_action_process(
     mut commands: Commands,
-    mut remove_event_writer: EventWriter<RemoveItemFromWorldEvent>,
+    config: Res<GameConfig>,
+    mut item_event_writer: EventWriter<WorldItemEvent>,
     animation_handles: Res<AnimationHandles>,
     mut query: Query<(
         Entity,
@@ -227,7 +377,7 @@ fn start_action_process(
                 }
                 PlayerState::Walk((x, y)) => {
                     *handle = animation_handles.walk.clone();
-                    timer.set_duration(std::time::Duration::from_millis(200));
+                    timer.set_duration(config.movement.walk_duration());
                     timer.reset();
 
                     let old = pos.clone();
@@ -240,7 +390,7 @@ fn start_action_process(
                 }
                 PlayerState::Pick(item_entity) => {
                     *handle = animation_handles.pick.clone();
-                    timer.set_duration(std::time::Duration::from_millis(500));
+                    timer.set_duration(config.movement.pick_duration());
                     timer.reset();
                     animation_state.reset();
 
@@ -249,24 +399,24 @@ fn start_action_process(
                         .entity(item_entity)
                         .remove::<Position>()
                         .insert(Owner(entity));
-                    remove_event_writer.send(RemoveItemFromWorldEvent(item_entity, *pos));
+                    item_event_writer.send(WorldItemEvent::Remove(item_entity, *pos));
                 }
                 PlayerState::Drop => {
                     *handle = animation_handles.pick.clone();
-                    timer.set_duration(std::time::Duration::from_millis(500));
+                    timer.set_duration(config.movement.pick_duration());
                     timer.reset();
                     animation_state.reset();
                 }
                 PlayerState::PickAndDrop(item_entity_ground) => {
                     *handle = animation_handles.pick.clone();
-                    timer.set_duration(std::time::Duration::from_millis(500));
+                    timer.set_duration(config.movement.pick_duration());
                     timer.reset();
                     animation_state.reset();
                     commands
                         .entity(item_entity_ground)
                         .remove::<Position>()
                         .insert(Owner(entity));
-                    remove_event_writer.send(RemoveItemFromWorldEvent(item_entity_ground, *pos));
+                    item_event_writer.send(WorldItemEvent::Remove(item_entity_ground, *pos));
                 }
             }
         },
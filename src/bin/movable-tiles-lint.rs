@@ -0,0 +1,155 @@
+// assets/ 以下のデータファイルをウィンドウを開かずに検査する
+//
+//     cargo run --bin movable-tiles-lint [assets_dir]
+//
+// 問題があれば一覧を出力して終了コード 1 で終わる
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use movable_tiles::plugins::{
+    autotile::AutotileRules,
+    config::GameConfigAsset,
+    items::ItemAssets,
+    validation::{self, AssetIssue},
+};
+
+#[derive(Default)]
+struct Lint {
+    root: PathBuf,
+    issues: Vec<AssetIssue>,
+    // id -> 最初に定義したファイル
+    item_ids: HashMap<String, String>,
+    checked: usize,
+}
+
+impl Lint {
+    fn texture_exists(&self, texture: &str) -> bool {
+        self.root.join(texture).is_file()
+    }
+
+    fn lint_item(&mut self, file: &str, bytes: &[u8]) {
+        let item: ItemAssets = match ron::de::from_bytes(bytes) {
+            Ok(item) => item,
+            Err(err) => {
+                self.issues
+                    .push(AssetIssue::new(file, None, err.to_string()));
+                return;
+            }
+        };
+        let issues = validation::validate_item(file, &item, |texture| self.texture_exists(texture));
+        self.issues.extend(issues);
+        let first_file = self.item_ids.get(&item.id).map(|file| file.as_str());
+        match validation::check_duplicate_id(file, &item, first_file) {
+            Some(issue) => self.issues.push(issue),
+            None => {
+                self.item_ids.insert(item.id.clone(), file.to_string());
+            }
+        }
+    }
+
+    fn lint_config(&mut self, file: &str, bytes: &[u8]) {
+        match ron::de::from_bytes::<GameConfigAsset>(bytes) {
+            Ok(config) => self.issues.extend(config.validate(file)),
+            Err(err) => self
+                .issues
+                .push(AssetIssue::new(file, None, err.to_string())),
+        }
+    }
+
+    fn lint_autotile(&mut self, file: &str, bytes: &[u8]) {
+        let rules: AutotileRules = match ron::de::from_bytes(bytes) {
+            Ok(rules) => rules,
+            Err(err) => {
+                self.issues
+                    .push(AssetIssue::new(file, None, err.to_string()));
+                return;
+            }
+        };
+        self.issues.extend(rules.validate(file));
+        for texture in rules.textures() {
+            if !self.texture_exists(texture) {
+                self.issues.push(AssetIssue::new(
+                    file,
+                    Some("textures"),
+                    format!("texture `{}` not found", texture),
+                ));
+            }
+        }
+    }
+
+    fn lint_file(&mut self, path: &Path) -> io::Result<()> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        // 新しいデータ形式はここに追加する
+        let lint: fn(&mut Lint, &str, &[u8]) = match extension {
+            "item" => Lint::lint_item,
+            "config" => Lint::lint_config,
+            "autotile" => Lint::lint_autotile,
+            _ => return Ok(()),
+        };
+        let file = path
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .display()
+            .to_string();
+        let bytes = fs::read(path)?;
+        lint(self, &file, &bytes);
+        self.checked += 1;
+        Ok(())
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let root = env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("assets"));
+
+    let mut files = Vec::new();
+    if let Err(err) = collect_files(&root, &mut files) {
+        eprintln!("{}: {}", root.display(), err);
+        process::exit(2);
+    }
+    files.sort();
+
+    let mut lint = Lint {
+        root,
+        ..Default::default()
+    };
+    for path in files.iter() {
+        if let Err(err) = lint.lint_file(path) {
+            let file = path.display().to_string();
+            lint.issues
+                .push(AssetIssue::new(&file, None, err.to_string()));
+        }
+    }
+
+    for issue in lint.issues.iter() {
+        println!("{}", issue);
+    }
+    if lint.issues.is_empty() {
+        println!("checked {} files, no problems found", lint.checked);
+    } else {
+        println!(
+            "checked {} files, found {} problem(s)",
+            lint.checked,
+            lint.issues.len()
+        );
+        process::exit(1);
+    }
+}
@@ -1,10 +1,30 @@
 use bevy::prelude::*;
 use movable_tiles::{
     agents::{ant::AntPlugin, player::PlayerPlugin},
-    plugins::{chunk::*, config::*, items::ItemsPlugin, textures::TexturePlugin, world::WorldPlugin},
+    plugins::{
+        aseprite::AsepritePlugin,
+        autotile::AutotilePlugin,
+        camera::CameraPlugin,
+        chunk::*,
+        clock::ClockPlugin,
+        config::*,
+        console::ConsolePlugin,
+        depth::DepthPlugin,
+        editor::EditorPlugin,
+        grid_debug::GridDebugPlugin,
+        history::HistoryPlugin,
+        hud::HudPlugin,
+        items::ItemsPlugin,
+        loading::LoadingPlugin,
+        picking::PickingPlugin,
+        replay::{ReplayMode, ReplayPlugin},
+        textures::TexturePlugin,
+        tooltip::TooltipPlugin,
+        ui::UiPlugin,
+        world::WorldPlugin,
+    },
 };
 
-
 fn main() {
     App::build()
         .insert_resource(WindowDescriptor {
@@ -15,19 +35,29 @@ fn main() {
             ..Default::default()
         })
         .add_plugins(DefaultPlugins)
+        .add_plugin(ClockPlugin)
+        .add_plugin(LoadingPlugin)
         .add_plugin(ConfigPlugin)
+        .add_plugin(AsepritePlugin)
         .add_plugin(WorldPlugin)
+        .add_plugin(HistoryPlugin)
+        .add_plugin(ReplayPlugin {
+            mode: ReplayMode::from_args(std::env::args().skip(1)),
+        })
+        .add_plugin(DepthPlugin)
         .add_plugin(TexturePlugin)
         .add_plugin(ItemsPlugin)
         .add_plugin(ChunkPlugin)
+        .add_plugin(AutotilePlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(PickingPlugin)
+        .add_plugin(GridDebugPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(UiPlugin)
+        .add_plugin(TooltipPlugin)
+        .add_plugin(HudPlugin)
+        .add_plugin(ConsolePlugin)
         .add_plugin(PlayerPlugin)
         .add_plugin(AntPlugin)
-        .add_startup_system(setup.system())
         .run();
 }
-
-fn setup(mut commands: Commands) {
-    let mut camera_bundle = OrthographicCameraBundle::new_2d();
-    camera_bundle.transform.scale = Vec3::new(0.5, 0.5, 1.0);
-    commands.spawn_bundle(camera_bundle);
-}
@@ -1,5 +1,23 @@
+pub mod aseprite;
+pub mod autotile;
+pub mod camera;
 pub mod config;
 pub mod chunk;
+pub mod clock;
+pub mod console;
+pub mod depth;
+pub mod editor;
+pub mod grid_debug;
+pub mod history;
+pub mod hud;
 pub mod items;
+pub mod level;
+pub mod loading;
+pub mod picking;
+pub mod replay;
+pub mod terrain;
 pub mod textures;
+pub mod tooltip;
+pub mod ui;
+pub mod validation;
 pub mod world;
\ No newline at end of file
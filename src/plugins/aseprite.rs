@@ -0,0 +1,133 @@
+use std::{collections::HashMap, time::Duration};
+
+use asefile::{AnimationDirection, AsepriteFile};
+use benimator::{Frame, SpriteSheetAnimation};
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    render::texture::{Extent3d, TextureDimension, TextureFormat},
+    utils::BoxedFuture,
+};
+
+// `*.aseprite` を読み込んで横一列の TextureAtlas とタグごとのアニメーションにする
+#[derive(TypeUuid, Debug, Default)]
+#[uuid = "8b2f6c1e-3f0a-4c55-9d47-2a6b1f0e9c31"]
+pub struct AsepriteAsset {
+    pub atlas: Handle<TextureAtlas>,
+    pub frame_durations: Vec<Duration>,
+    pub animations: HashMap<String, Handle<SpriteSheetAnimation>>,
+}
+
+impl AsepriteAsset {
+    pub fn animation(&self, tag: &str) -> Option<Handle<SpriteSheetAnimation>> {
+        self.animations.get(tag).cloned()
+    }
+}
+
+#[derive(Default)]
+pub struct AsepriteLoader;
+
+impl AssetLoader for AsepriteLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let ase = AsepriteFile::read(bytes)?;
+            let (width, height) = (ase.width(), ase.height());
+            let frame_count = ase.num_frames();
+
+            // 全フレームを横に並べた 1 枚のテクスチャにする
+            let stride = width * frame_count as usize * 4;
+            let mut data = vec![0; stride * height];
+            let mut frame_durations = Vec::new();
+            for i in 0..frame_count {
+                let frame = ase.frame(i);
+                let image = frame.image();
+                let raw = image.as_raw();
+                for y in 0..height {
+                    let src = y * width * 4;
+                    let dst = y * stride + i as usize * width * 4;
+                    data[dst..dst + width * 4].copy_from_slice(&raw[src..src + width * 4]);
+                }
+                frame_durations.push(Duration::from_millis(frame.duration() as u64));
+            }
+
+            let texture = Texture::new(
+                Extent3d::new(width as u32 * frame_count, height as u32, 1),
+                TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+            );
+            load_context.set_labeled_asset("texture", LoadedAsset::new(texture));
+            let texture_handle: Handle<Texture> =
+                load_context.get_handle(AssetPath::new_ref(load_context.path(), Some("texture")));
+
+            let atlas = TextureAtlas::from_grid(
+                texture_handle,
+                Vec2::new(width as f32, height as f32),
+                frame_count as usize,
+                1,
+            );
+            load_context.set_labeled_asset("atlas", LoadedAsset::new(atlas));
+            let atlas: Handle<TextureAtlas> =
+                load_context.get_handle(AssetPath::new_ref(load_context.path(), Some("atlas")));
+
+            let mut animations = HashMap::new();
+            for i in 0..ase.num_tags() {
+                let tag = ase.tag(i);
+                let mut indices: Vec<u32> = (tag.from_frame()..=tag.to_frame()).collect();
+                match tag.animation_direction() {
+                    AnimationDirection::Forward => (),
+                    AnimationDirection::Reverse => indices.reverse(),
+                    AnimationDirection::PingPong => {
+                        // 0 1 2 3 -> 0 1 2 3 2 1
+                        let back: Vec<u32> = indices
+                            .iter()
+                            .rev()
+                            .skip(1)
+                            .take(indices.len().saturating_sub(2))
+                            .copied()
+                            .collect();
+                        indices.extend(back);
+                    }
+                }
+                let frames = indices
+                    .into_iter()
+                    .map(|index| Frame::new(index, frame_durations[index as usize]))
+                    .collect();
+
+                let label = format!("tag/{}", tag.name());
+                load_context.set_labeled_asset(
+                    &label,
+                    LoadedAsset::new(SpriteSheetAnimation::from_frames(frames)),
+                );
+                let handle =
+                    load_context.get_handle(AssetPath::new_ref(load_context.path(), Some(&label)));
+                animations.insert(tag.name().to_string(), handle);
+            }
+
+            load_context.set_default_asset(LoadedAsset::new(AsepriteAsset {
+                atlas,
+                frame_durations,
+                animations,
+            }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["aseprite", "ase"]
+    }
+}
+
+pub struct AsepritePlugin;
+
+impl Plugin for AsepritePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_asset::<AsepriteAsset>()
+            .init_asset_loader::<AsepriteLoader>();
+    }
+}
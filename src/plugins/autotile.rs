@@ -0,0 +1,499 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{prelude::*, reflect::TypeUuid};
+use bevy_asset_ron::RonAssetPlugin;
+use bevy_tilemap::{prelude::*, Tilemap};
+use serde::Deserialize;
+
+use super::{
+    chunk::{self, TileChangedEvent, TileLayer},
+    config::GameConfig,
+    items::{Item, TextureOverride},
+    loading::{AppState, LoadingAssets},
+    validation::{self, AssetIssue},
+    world::{GameWorld, Position, WorldItemEvent},
+};
+
+const RULES_FILE: &str = "data.autotile";
+
+// 北から時計回り. Four は辺だけ, Eight は角も見る
+const EDGES: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const EDGES_AND_CORNERS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+#[derive(Deserialize, TypeUuid, Debug, Default, Clone, PartialEq)]
+#[uuid = "821fa44e-19d0-42d0-aaa5-94eea8929b76"]
+#[serde(default, deny_unknown_fields)]
+pub struct AutotileRules {
+    pub sets: Vec<AutotileRuleSet>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutotileTarget {
+    // members はアイテムの id
+    Item,
+    // members はその層のタイルの画像のパス
+    Tile(TileLayer),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighbourhood {
+    // ビットは北, 東, 南, 西の順 (0..16)
+    Four,
+    // ビットは北, 北東, 東, ... 北西の順. 角は両隣の辺がつながっているときだけ立つ (47 通り)
+    Eight,
+}
+
+impl Default for Neighbourhood {
+    fn default() -> Self {
+        Neighbourhood::Four
+    }
+}
+
+impl Neighbourhood {
+    // 仲間の隣のビットを立てる
+    pub fn mask(self, pos: Position, mut is_member: impl FnMut(Position) -> bool) -> u8 {
+        let offsets: &[(i32, i32)] = match self {
+            Neighbourhood::Four => &EDGES,
+            Neighbourhood::Eight => &EDGES_AND_CORNERS,
+        };
+        let neighbours = offsets
+            .iter()
+            .map(|(dx, dy)| {
+                is_member(Position {
+                    x: pos.x + dx,
+                    y: pos.y + dy,
+                })
+            })
+            .collect::<Vec<_>>();
+        self.normalize(
+            neighbours
+                .iter()
+                .enumerate()
+                .filter(|(_, member)| **member)
+                .fold(0, |mask, (bit, _)| mask | 1 << bit),
+        )
+    }
+
+    // 辺が欠けている角のビットを落とす. 戻り値が mask と同じなら起こりうる mask
+    pub fn normalize(self, mask: u8) -> u8 {
+        match self {
+            Neighbourhood::Four => mask & 0b1111,
+            Neighbourhood::Eight => (0..8).fold(0, |normalized, bit| {
+                let has = |bit: u32| mask & 1 << (bit % 8) != 0;
+                let corner = bit % 2 == 1;
+                if has(bit) && (!corner || (has(bit + 7) && has(bit + 1))) {
+                    normalized | 1 << bit
+                } else {
+                    normalized
+                }
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AutotileRuleSet {
+    pub name: String,
+    pub target: AutotileTarget,
+    #[serde(default)]
+    pub neighbours: Neighbourhood,
+    // つながる仲間. タイルでは付け替えた後の画像 (textures と fallback) も仲間に数える
+    pub members: Vec<String>,
+    // mask -> 表示する画像
+    pub textures: HashMap<u8, String>,
+    // textures に無い mask のときの画像. 無ければ元の画像のまま
+    #[serde(default)]
+    pub fallback: Option<String>,
+}
+
+impl AutotileRuleSet {
+    pub fn is_member(&self, value: &str) -> bool {
+        self.members.iter().any(|member| member == value)
+            || (matches!(self.target, AutotileTarget::Tile(_))
+                && self
+                    .textures
+                    .values()
+                    .chain(self.fallback.iter())
+                    .any(|texture| texture == value))
+    }
+
+    pub fn texture(&self, mask: u8) -> Option<&str> {
+        self.textures
+            .get(&mask)
+            .or_else(|| self.fallback.as_ref())
+            .map(|texture| texture.as_str())
+    }
+}
+
+impl AutotileRules {
+    // 画像の有無は呼び出し側が textures() で調べる
+    pub fn validate(&self, file: &str) -> Vec<AssetIssue> {
+        let mut issues = Vec::new();
+        let mut names = HashSet::new();
+        for (i, set) in self.sets.iter().enumerate() {
+            let mut check = |ok: bool, field: &str, reason: String| {
+                if !ok {
+                    let field = format!("sets[{}].{}", i, field);
+                    issues.push(AssetIssue::new(file, Some(&field), reason));
+                }
+            };
+            check(
+                !set.name.is_empty(),
+                "name",
+                "must not be empty".to_string(),
+            );
+            check(
+                names.insert(set.name.as_str()),
+                "name",
+                format!("duplicate name `{}`", set.name),
+            );
+            check(
+                !set.members.is_empty(),
+                "members",
+                "must not be empty".to_string(),
+            );
+            let mut masks = set.textures.keys().copied().collect::<Vec<_>>();
+            masks.sort_unstable();
+            for mask in masks {
+                check(
+                    set.neighbours.normalize(mask) == mask,
+                    "textures",
+                    format!(
+                        "mask {} can not occur with {:?} neighbours",
+                        mask, set.neighbours
+                    ),
+                );
+            }
+        }
+        issues
+    }
+
+    pub fn textures(&self) -> impl Iterator<Item = &str> {
+        self.sets.iter().flat_map(|set| {
+            set.textures
+                .values()
+                .chain(set.fallback.iter())
+                .map(|texture| texture.as_str())
+        })
+    }
+}
+
+// Resource
+#[derive(Default)]
+pub struct Autotiles {
+    handle: Handle<AutotileRules>,
+    rules: AutotileRules,
+    // 規則が変わったので全部付け直す
+    refresh_items: bool,
+    refresh_tiles: bool,
+    // 付け直すセル. アイテムがまだ spawn されていなければ次のフレームに残す
+    dirty_items: HashSet<Position>,
+    dirty_tiles: HashSet<(Position, TileLayer)>,
+}
+
+impl Autotiles {
+    pub fn rules(&self) -> &AutotileRules {
+        &self.rules
+    }
+}
+
+fn with_neighbours(pos: Position) -> impl Iterator<Item = Position> {
+    std::iter::once((0, 0))
+        .chain(EDGES_AND_CORNERS.iter().copied())
+        .map(move |(dx, dy)| Position {
+            x: pos.x + dx,
+            y: pos.y + dy,
+        })
+}
+
+pub struct AutotilePlugin;
+
+impl Plugin for AutotilePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(
+            // load `*.autotile` files
+            RonAssetPlugin::<AutotileRules>::new(&["autotile"]),
+        )
+        .init_resource::<Autotiles>()
+        .add_startup_system(setup.system())
+        .add_system(load_rules.system())
+        // Update で置かれたり消されたりしたアイテムが spawn されて item_map に入ってから付け直す
+        .add_system_set_to_stage(
+            CoreStage::PostUpdate,
+            SystemSet::on_update(AppState::Playing)
+                .with_system(update_items.system().after("world_events"))
+                .with_system(update_tiles.system()),
+        );
+    }
+}
+
+fn setup(
+    mut autotiles: ResMut<Autotiles>,
+    mut loading_assets: ResMut<LoadingAssets>,
+    server: Res<AssetServer>,
+) {
+    autotiles.handle = server.load(RULES_FILE);
+    loading_assets.add(&autotiles.handle);
+    server.watch_for_changes().unwrap();
+}
+
+fn load_rules(
+    mut ev_asset: EventReader<AssetEvent<AutotileRules>>,
+    mut autotiles: ResMut<Autotiles>,
+    assets: Res<Assets<AutotileRules>>,
+) {
+    for ev in ev_asset.iter() {
+        let handle = match ev {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != autotiles.handle {
+            continue;
+        }
+        if let Some(new) = assets.get(handle) {
+            let issues = new.validate(RULES_FILE);
+            if !issues.is_empty() {
+                // 不正な規則は反映せず直前の規則を使い続ける
+                validation::report(&issues);
+                continue;
+            }
+            autotiles.rules = new.clone();
+            autotiles.refresh_items = true;
+            autotiles.refresh_tiles = true;
+        }
+    }
+}
+
+fn update_items(
+    mut commands: Commands,
+    mut autotiles: ResMut<Autotiles>,
+    mut item_events: EventReader<WorldItemEvent>,
+    asset_server: Res<AssetServer>,
+    world_query: Query<&GameWorld>,
+    item_query: Query<(&Item, Option<&TextureOverride>)>,
+) {
+    let autotiles = &mut *autotiles;
+    let world = match world_query.iter().next() {
+        Some(world) => world,
+        None => return,
+    };
+
+    // 元の画像に戻したアイテム. 別のセルに置かれていればそちらで付け直す
+    let mut removed = HashSet::new();
+    for ev in item_events.iter() {
+        let pos = match *ev {
+            WorldItemEvent::Add(_, pos) => pos,
+            WorldItemEvent::Remove(entity, pos) => {
+                if item_query.get(entity).is_ok() && removed.insert(entity) {
+                    commands.entity(entity).remove::<TextureOverride>();
+                }
+                pos
+            }
+        };
+        autotiles.dirty_items.extend(with_neighbours(pos));
+    }
+    if std::mem::take(&mut autotiles.refresh_items) {
+        let cells = world.item_map.keys().copied().collect::<Vec<_>>();
+        autotiles.dirty_items.extend(cells);
+    }
+    if autotiles.dirty_items.is_empty() {
+        return;
+    }
+
+    // まだ spawn されていないアイテムは Err
+    let item_id = |pos: Position| match world.item_map.get(&pos) {
+        Some(entity) => item_query
+            .get(*entity)
+            .map(|(item, _)| Some(item.id()))
+            .map_err(|_| ()),
+        None => Ok(None),
+    };
+    let rules = &autotiles.rules;
+    let mut retry = HashSet::new();
+    for pos in autotiles.dirty_items.drain() {
+        let entity = match world.item_map.get(&pos) {
+            Some(entity) => *entity,
+            None => continue,
+        };
+        let (item, texture_override) = match item_query.get(entity) {
+            // 同じフレームで外した分はまだ付いて見える
+            Ok((item, _)) if removed.contains(&entity) => (item, None),
+            Ok(item) => item,
+            Err(_) => {
+                retry.insert(pos);
+                continue;
+            }
+        };
+        let set = rules
+            .sets
+            .iter()
+            .find(|set| set.target == AutotileTarget::Item && set.is_member(item.id()));
+        let texture = match set {
+            Some(set) => {
+                let mut unresolved = false;
+                let mask = set.neighbours.mask(pos, |pos| match item_id(pos) {
+                    Ok(item_id) => item_id.map_or(false, |item_id| set.is_member(item_id)),
+                    Err(()) => {
+                        unresolved = true;
+                        false
+                    }
+                });
+                if unresolved {
+                    retry.insert(pos);
+                    continue;
+                }
+                set.texture(mask)
+                    .map(|texture| asset_server.get_handle(texture))
+            }
+            None => None,
+        };
+        match (texture, texture_override) {
+            (Some(texture), Some(current)) if current.0 == texture => {}
+            (Some(texture), _) => {
+                commands.entity(entity).insert(TextureOverride(texture));
+            }
+            (None, Some(_)) => {
+                commands.entity(entity).remove::<TextureOverride>();
+            }
+            (None, None) => {}
+        }
+    }
+    autotiles.dirty_items = retry;
+}
+
+// タイルの規則を atlas の index に直したもの
+struct TileRuleSet {
+    layer: TileLayer,
+    neighbours: Neighbourhood,
+    members: HashSet<usize>,
+    textures: HashMap<u8, usize>,
+    fallback: Option<usize>,
+}
+
+fn tile_rule_sets(
+    rules: &AutotileRules,
+    atlas: &TextureAtlas,
+    asset_server: &AssetServer,
+) -> Vec<TileRuleSet> {
+    let index = |texture: &String| chunk::texture_index(atlas, asset_server, texture);
+    rules
+        .sets
+        .iter()
+        .filter_map(|set| {
+            let layer = match set.target {
+                AutotileTarget::Tile(layer) => layer,
+                _ => return None,
+            };
+            let textures = set
+                .textures
+                .iter()
+                .filter_map(|(mask, texture)| Some((*mask, index(texture)?)))
+                .collect::<HashMap<_, _>>();
+            let fallback = set.fallback.as_ref().and_then(index);
+            Some(TileRuleSet {
+                layer,
+                neighbours: set.neighbours,
+                members: set
+                    .members
+                    .iter()
+                    .filter_map(index)
+                    .chain(textures.values().copied())
+                    .chain(fallback)
+                    .collect(),
+                fallback,
+                textures,
+            })
+        })
+        .collect()
+}
+
+fn update_tiles(
+    mut autotiles: ResMut<Autotiles>,
+    mut events: EventReader<TileChangedEvent>,
+    config: Res<GameConfig>,
+    asset_server: Res<AssetServer>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut map_query: Query<&mut Tilemap>,
+) {
+    let autotiles = &mut *autotiles;
+    let layers = autotiles
+        .rules
+        .sets
+        .iter()
+        .filter_map(|set| match set.target {
+            AutotileTarget::Tile(layer) => Some(layer),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    for TileChangedEvent(pos, layer) in events.iter() {
+        if layers.contains(layer) {
+            let cells = with_neighbours(*pos).map(|pos| (pos, *layer));
+            autotiles.dirty_tiles.extend(cells);
+        }
+    }
+    if std::mem::take(&mut autotiles.refresh_tiles) {
+        let (min, max) = chunk::tile_range(&config.world);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let cells = layers.iter().map(|layer| (Position { x, y }, *layer));
+                autotiles.dirty_tiles.extend(cells);
+            }
+        }
+    }
+    if autotiles.dirty_tiles.is_empty() {
+        return;
+    }
+
+    for mut map in map_query.iter_mut() {
+        let atlas = match texture_atlases.get(map.texture_atlas()) {
+            Some(atlas) => atlas,
+            None => continue,
+        };
+        let sets = tile_rule_sets(&autotiles.rules, atlas, &asset_server);
+        for (pos, layer) in autotiles.dirty_tiles.iter() {
+            let sprite_order = layer.sprite_order();
+            let (index, tint) = match map.get_tile((pos.x, pos.y), sprite_order) {
+                Some(tile) => (tile.index, tile.color),
+                None => continue,
+            };
+            let set = match sets
+                .iter()
+                .find(|set| set.layer == *layer && set.members.contains(&index))
+            {
+                Some(set) => set,
+                None => continue,
+            };
+            let mask = set.neighbours.mask(*pos, |pos| {
+                map.get_tile((pos.x, pos.y), sprite_order)
+                    .map_or(false, |tile| set.members.contains(&tile.index))
+            });
+            let new_index = match set.textures.get(&mask).copied().or(set.fallback) {
+                Some(new_index) if new_index != index => new_index,
+                _ => continue,
+            };
+            let result = map.insert_tile(Tile {
+                point: (pos.x, pos.y),
+                sprite_order,
+                sprite_index: new_index,
+                tint,
+            });
+            if let Err(err) = result {
+                warn!(
+                    "failed to autotile ({}, {}) on {:?}: {:?}",
+                    pos.x, pos.y, layer, err
+                );
+            }
+        }
+    }
+    autotiles.dirty_tiles.clear();
+}
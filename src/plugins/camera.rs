@@ -0,0 +1,159 @@
+use bevy::{input::mouse::MouseWheel, prelude::*};
+
+use crate::agents::player::PlayerState;
+
+use super::{
+    chunk::MapBounds,
+    clock::GameClock,
+    config::{ConfigChanged, GameConfig},
+};
+
+pub const CAMERA_Z: f32 = 1000.0 - 0.1;
+
+pub struct MainCamera {
+    // 整数倍率のみ (ピクセルパーフェクト)
+    pub zoom: u32,
+    target: Vec2,
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(setup.system())
+            .add_system(apply_config.system().label("camera_config").after("config"))
+            .add_system(zoom.system().label("zoom").after("camera_config"))
+            .add_system(
+                follow
+                    .system()
+                    .label("camera")
+                    .after("position")
+                    .after("zoom"),
+            );
+    }
+}
+
+fn setup(mut commands: Commands, config: Res<GameConfig>) {
+    let mut camera_bundle = OrthographicCameraBundle::new_2d();
+    camera_bundle.transform.scale = zoom_scale(config.camera.zoom);
+    commands.spawn_bundle(camera_bundle).insert(MainCamera {
+        zoom: config.camera.zoom,
+        target: Vec2::ZERO,
+    });
+}
+
+fn zoom_scale(zoom: u32) -> Vec3 {
+    Vec3::new(1.0 / zoom as f32, 1.0 / zoom as f32, 1.0)
+}
+
+fn apply_config(
+    mut events: EventReader<ConfigChanged>,
+    config: Res<GameConfig>,
+    mut query: Query<(&mut MainCamera, &mut Transform)>,
+) {
+    for ev in events.iter() {
+        if !ev.contains("camera") {
+            continue;
+        }
+        let camera_config = &config.camera;
+        query.iter_mut().for_each(|(mut camera, mut transform)| {
+            camera.zoom = if ev.contains("camera.zoom") {
+                camera_config.zoom
+            } else {
+                camera
+                    .zoom
+                    .max(camera_config.min_zoom)
+                    .min(camera_config.max_zoom)
+            };
+            transform.scale = zoom_scale(camera.zoom);
+        });
+    }
+}
+
+fn zoom(
+    config: Res<GameConfig>,
+    key_input: Res<Input<KeyCode>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut query: Query<(&mut MainCamera, &mut Transform)>,
+) {
+    let mut step = 0;
+    for ev in wheel_events.iter() {
+        if ev.y > 0.0 {
+            step += 1;
+        } else if ev.y < 0.0 {
+            step -= 1;
+        }
+    }
+    if key_input.just_pressed(KeyCode::Equals) || key_input.just_pressed(KeyCode::NumpadAdd) {
+        step += 1;
+    }
+    if key_input.just_pressed(KeyCode::Minus) || key_input.just_pressed(KeyCode::NumpadSubtract) {
+        step -= 1;
+    }
+    if step == 0 {
+        return;
+    }
+    query.iter_mut().for_each(|(mut camera, mut transform)| {
+        camera.zoom = (camera.zoom as i32 + step)
+            .max(config.camera.min_zoom as i32)
+            .min(config.camera.max_zoom as i32) as u32;
+        transform.scale = zoom_scale(camera.zoom);
+    });
+}
+
+fn follow(
+    config: Res<GameConfig>,
+    clock: Res<GameClock>,
+    windows: Res<Windows>,
+    bounds: Option<Res<MapBounds>>,
+    mut query: QuerySet<(
+        Query<(&Transform,), (With<PlayerState>,)>,
+        Query<(&mut MainCamera, &mut Transform)>,
+    )>,
+) {
+    let mut player = None;
+    query.q0().iter().for_each(|(transform,)| {
+        player = Some(transform.translation.truncate());
+    });
+    let player = match player {
+        Some(player) => player,
+        None => return,
+    };
+    let half_window = windows
+        .get_primary()
+        .map(|window| Vec2::new(window.width(), window.height()) / 2.0)
+        .unwrap_or_default();
+
+    query
+        .q1_mut()
+        .iter_mut()
+        .for_each(|(mut camera, mut transform)| {
+            // dead zone の外に出た分だけ目標を動かす
+            let half_dead_zone = Vec2::from(config.camera.dead_zone) / 2.0;
+            let diff = player - camera.target;
+            let excess = diff.abs() - half_dead_zone;
+            if excess.x > 0.0 {
+                camera.target.x += excess.x * diff.x.signum();
+            }
+            if excess.y > 0.0 {
+                camera.target.y += excess.y * diff.y.signum();
+            }
+
+            let current = transform.translation.truncate();
+            let t = (config.camera.follow_speed * clock.delta_seconds()).min(1.0);
+            let mut next = current + (camera.target - current) * t;
+
+            if let Some(bounds) = &bounds {
+                let half_view = half_window / camera.zoom as f32;
+                next = bounds.clamp(next, half_view);
+            }
+
+            // 拡大後の 1 ピクセル単位に揃える
+            let pixel = 1.0 / camera.zoom as f32;
+            transform.translation = Vec3::new(
+                (next.x / pixel).round() * pixel,
+                (next.y / pixel).round() * pixel,
+                CAMERA_Z,
+            );
+        });
+}
@@ -1,54 +1,243 @@
-use bevy::{prelude::*, sprite::TextureAtlasBuilder};
+use std::collections::HashMap;
+
+use bevy::prelude::*;
 use bevy_tilemap::{prelude::*, Tilemap};
-use rand::{thread_rng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::agents::player::PlayerState;
 
-use super::textures::SpriteHandles;
+use super::{
+    config::{GameConfig, WorldConfig},
+    depth::{self, RenderLayer},
+    items::{self, ItemDataMap},
+    loading::AppState,
+    terrain::{self, TerrainGenerator},
+    textures::{self, SpriteHandles},
+    validation::{self, AssetIssue},
+    world::{Position, WorldItemEvent, WorldSeed},
+};
 
-#[derive(Default, Clone)]
-struct MapState {
-    map_loaded: bool,
-    atlas_loaded: bool,
+// Resource
+// 生成済みタイルの範囲 (ワールド座標, タイルの端まで)
+#[derive(Clone, Copy, Debug)]
+pub struct MapBounds {
+    pub min: Vec2,
+    pub max: Vec2,
 }
 
-pub struct ChunkPlugin;
+impl MapBounds {
+    pub fn clamp(&self, center: Vec2, half_extent: Vec2) -> Vec2 {
+        let clamp_axis = |value: f32, min: f32, max: f32, half: f32| {
+            if max - min < half * 2.0 {
+                (min + max) / 2.0
+            } else {
+                value.max(min + half).min(max - half)
+            }
+        };
+        Vec2::new(
+            clamp_axis(center.x, self.min.x, self.max.x, half_extent.x),
+            clamp_axis(center.y, self.min.y, self.max.y, half_extent.y),
+        )
+    }
+}
 
-impl Plugin for ChunkPlugin {
-    fn build(&self, app: &mut AppBuilder) {
-        app.add_plugins(TilemapDefaultPlugins)
-            .init_resource::<MapState>()
-            .add_system(load.system())
-            .add_system(build_world.system());
+// タイルの層. 後ろのものほど手前に描く
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TileLayer {
+    Ground,
+    // 草やひび割れ. 地面の上に重ねる
+    Decoration,
+    // 霧. 選択の強調は picking の HoverHighlight が描く
+    Overlay,
+}
+
+impl TileLayer {
+    pub const ALL: [TileLayer; 3] = [TileLayer::Ground, TileLayer::Decoration, TileLayer::Overlay];
+
+    pub fn sprite_order(self) -> usize {
+        self as usize
     }
 }
 
-fn load(
-    mut commands: Commands,
-    sprite_handles: Res<SpriteHandles>,
-    mut map_state: ResMut<MapState>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    mut textures: ResMut<Assets<Texture>>,
+impl Default for TileLayer {
+    fn default() -> Self {
+        TileLayer::Ground
+    }
+}
+
+const Z_LAYERS: usize = TileLayer::ALL.len();
+
+// 装飾は地面とは別の乱数列で決める (装飾の規則を変えても地形が変わらないように)
+const DECORATION_SEED_SALT: u64 = 0x6465_636f;
+
+// texture が None ならそのセルの layer のタイルを消す
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetTileEvent {
+    pub pos: Position,
+    pub layer: TileLayer,
+    pub texture: Option<String>,
+}
+
+// タイルが置き換わった (オートタイルが周りを付け直す). 生成では送らない
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileChangedEvent(pub Position, pub TileLayer);
+
+// Resource
+// 霧の画像の atlas の index. 霧を出していなければ None
+#[derive(Default)]
+struct Fog {
+    index: Option<usize>,
+}
+
+pub fn texture_index(
+    atlas: &TextureAtlas,
+    asset_server: &AssetServer,
+    texture: &str,
+) -> Option<usize> {
+    atlas.get_texture_index(&asset_server.get_handle(texture))
+}
+
+// pos の layer に置かれているタイルの画像のパス
+pub fn tile_texture(
+    map: &mut Tilemap,
+    texture_atlases: &Assets<TextureAtlas>,
+    asset_server: &AssetServer,
+    pos: Position,
+    layer: TileLayer,
+) -> Option<String> {
+    let index = map.get_tile((pos.x, pos.y), layer.sprite_order())?.index;
+    let atlas = texture_atlases.get(map.texture_atlas())?;
+    let (handle, _) = atlas
+        .texture_handles
+        .as_ref()?
+        .iter()
+        .find(|(_, i)| **i == index)?;
+    let path = asset_server.get_handle_path(handle)?;
+    Some(path.path().to_string_lossy().into_owned())
+}
+
+// pos の layer にタイルを置く. texture が None なら消す. atlas に無い画像は置かない
+pub fn set_tile(
+    map: &mut Tilemap,
+    texture_atlases: &Assets<TextureAtlas>,
+    asset_server: &AssetServer,
+    pos: Position,
+    layer: TileLayer,
+    texture: Option<&str>,
 ) {
-    if map_state.atlas_loaded || !sprite_handles.textures_loaded {
-        return;
+    let result = match texture {
+        Some(texture) => {
+            let index = match texture_atlases
+                .get(map.texture_atlas())
+                .and_then(|atlas| texture_index(atlas, asset_server, texture))
+            {
+                Some(index) => index,
+                None => {
+                    warn!("texture `{}` is not in the texture atlas", texture);
+                    return;
+                }
+            };
+            map.insert_tile(Tile {
+                point: (pos.x, pos.y),
+                sprite_order: layer.sprite_order(),
+                sprite_index: index,
+                ..Default::default()
+            })
+        }
+        None => map.clear_tile((pos.x, pos.y), layer.sprite_order()),
+    };
+    if let Err(err) = result {
+        warn!(
+            "failed to set {:?} tile at ({}, {}): {:?}",
+            layer, pos.x, pos.y, err
+        );
     }
+}
+
+// 生成するタイルの範囲 (両端を含む). chunk_ranges を全部合わせたもの
+pub fn tile_range(world: &WorldConfig) -> (Position, Position) {
+    chunk_ranges(world).into_iter().fold(
+        (
+            Position {
+                x: i32::MAX,
+                y: i32::MAX,
+            },
+            Position {
+                x: i32::MIN,
+                y: i32::MIN,
+            },
+        ),
+        |(min, max), (chunk_min, chunk_max)| {
+            (
+                Position {
+                    x: min.x.min(chunk_min.x),
+                    y: min.y.min(chunk_min.y),
+                },
+                Position {
+                    x: max.x.max(chunk_max.x),
+                    y: max.y.max(chunk_max.y),
+                },
+            )
+        },
+    )
+}
 
-    let mut texture_atlas_builder = TextureAtlasBuilder::default();
-    for handle in sprite_handles.textures_handles.iter() {
-        let texture = textures.get(handle).unwrap();
-        texture_atlas_builder.add_texture(handle.clone_weak().typed::<Texture>(), texture);
+// spawn するチャンクごとのタイルの範囲 (両端を含む). bevy_tilemap はチャンク (0, 0) の中心を原点に置く
+pub fn chunk_ranges(world: &WorldConfig) -> Vec<(Position, Position)> {
+    let (chunks_x, chunks_y) = (world.chunks.0 as i32, world.chunks.1 as i32);
+    let (width, height) = (world.chunk_size.0 as i32, world.chunk_size.1 as i32);
+    let mut ranges = Vec::new();
+    for chunk_y in 0..chunks_y {
+        for chunk_x in 0..chunks_x {
+            let min = Position {
+                x: (chunk_x - chunks_x / 2) * width - width / 2,
+                y: (chunk_y - chunks_y / 2) * height - height / 2,
+            };
+            ranges.push((
+                min,
+                Position {
+                    x: min.x + width - 1,
+                    y: min.y + height - 1,
+                },
+            ));
+        }
     }
+    ranges
+}
+
+pub struct ChunkPlugin;
 
-    let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
-    let atlas_handle = texture_atlases.add(texture_atlas);
+impl Plugin for ChunkPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugins(TilemapDefaultPlugins)
+            .add_event::<SetTileEvent>()
+            .add_event::<TileChangedEvent>()
+            .init_resource::<Fog>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::Generating).with_system(load.system().after("atlas")),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Generating).with_system(build_world.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(rebuild_atlas.system())
+                    .with_system(apply_set_tile.system().label("set_tile"))
+                    .with_system(reveal_fog.system().after("set_tile")),
+            );
+    }
+}
 
+fn load(mut commands: Commands, config: Res<GameConfig>, sprite_handles: Res<SpriteHandles>) {
     let tilemap = Tilemap::builder()
         .auto_chunk()
         .topology(GridTopology::Square)
-        .dimensions(3, 3)
-        .chunk_dimensions(64, 64, 1)
+        .dimensions(config.world.chunks.0, config.world.chunks.1)
+        .chunk_dimensions(config.world.chunk_size.0, config.world.chunk_size.1, 1)
         .texture_dimensions(32, 32)
-        .z_layers(3)
-        .texture_atlas(atlas_handle)
+        .z_layers(Z_LAYERS)
+        .texture_atlas(sprite_handles.atlas.clone())
         .finish()
         .unwrap();
 
@@ -59,7 +248,7 @@ fn load(
             is_transparent: true,
         },
         transform: Transform {
-            translation: Vec3::new(16.0, 16.0, 0.0),
+            translation: Vec3::new(16.0, 16.0, depth::sort_z(RenderLayer::Ground, 0.0, 0.0)),
             scale: Vec3::new(1.0, 1.0, 1.0),
             rotation: Quat::from_rotation_x(0.0),
         },
@@ -70,58 +259,291 @@ fn load(
         .spawn()
         .insert_bundle(tilemap_components)
         .insert(Timer::from_seconds(0.075, true));
+}
 
-    map_state.atlas_loaded = true;
+// data.config の field に書かれた画像が atlas に無い
+fn missing_texture(field: &str, texture: &str) -> AssetIssue {
+    AssetIssue::new(
+        "data.config",
+        Some(field),
+        format!(
+            "texture `{}` is not in the texture atlas (textures/)",
+            texture
+        ),
+    )
 }
 
+// 装飾の規則を atlas の index に直す. atlas に無い画像の規則は報告して捨てる
+fn decoration_rules(
+    config: &GameConfig,
+    atlas: &TextureAtlas,
+    asset_server: &AssetServer,
+) -> Vec<(usize, Vec<usize>, f32)> {
+    let mut issues = Vec::new();
+    let mut index = |texture: &str| {
+        let index = texture_index(atlas, asset_server, texture);
+        if index.is_none() {
+            issues.push(missing_texture("world.decorations", texture));
+        }
+        index
+    };
+    let rules = config
+        .world
+        .decorations
+        .iter()
+        .filter_map(|rule| {
+            let on = rule
+                .on
+                .iter()
+                .filter_map(|texture| index(texture))
+                .collect::<Vec<_>>();
+            Some((index(&rule.texture)?, on, rule.chance))
+        })
+        .collect();
+    validation::report(&issues);
+    rules
+}
+
+// チャンクごとに seed から地形を生成する. チャンクの中身は他のチャンクに依らない
+#[allow(clippy::too_many_arguments)]
 fn build_world(
-    mut map_state: ResMut<MapState>,
+    mut commands: Commands,
+    mut state: ResMut<State<AppState>>,
+    mut item_events: EventWriter<WorldItemEvent>,
     texture_atlases: Res<Assets<TextureAtlas>>,
     asset_server: Res<AssetServer>,
+    item_data: Res<ItemDataMap>,
+    seed: Res<WorldSeed>,
+    config: Res<GameConfig>,
+    mut fog: ResMut<Fog>,
     mut query: Query<(&mut Tilemap,)>,
 ) {
-    if map_state.map_loaded || !map_state.atlas_loaded {
+    // load で spawn した Tilemap は次のフレームから見える
+    for (mut map,) in query.iter_mut() {
+        let texture_atlas = texture_atlases.get(map.texture_atlas()).unwrap();
+        let decorations = decoration_rules(&config, texture_atlas, &asset_server);
+        fog.index = config.world.fog.as_ref().and_then(|fog_config| {
+            let index = texture_index(texture_atlas, &asset_server, &fog_config.texture);
+            if index.is_none() {
+                validation::report(&[missing_texture("world.fog.texture", &fog_config.texture)]);
+            }
+            index
+        });
+
+        // 無い画像やアイテムは一度だけ報告してそのセルを飛ばす
+        let mut issues = Vec::new();
+        let mut tile_indices: HashMap<String, Option<usize>> = HashMap::new();
+        let mut unknown_items = Vec::new();
+        let generator = TerrainGenerator::new(seed.0, &config.terrain);
+        let mut tiles = Vec::new();
+        for (min, max) in chunk_ranges(&config.world) {
+            let content = generator.generate_chunk(min, max);
+            let mut decoration_rng =
+                StdRng::seed_from_u64(terrain::chunk_seed(seed.0, min) ^ DECORATION_SEED_SALT);
+            for (pos, texture) in content.tiles {
+                let sprite_index = *tile_indices.entry(texture).or_insert_with_key(|texture| {
+                    let index = texture_index(texture_atlas, &asset_server, texture);
+                    if index.is_none() {
+                        issues.push(missing_texture("terrain.biomes", texture));
+                    }
+                    index
+                });
+                let sprite_index = match sprite_index {
+                    Some(sprite_index) => sprite_index,
+                    None => continue,
+                };
+                let point = (pos.x, pos.y);
+                // 最初に当たった規則を 1 つだけ置く
+                let decoration = decorations.iter().find(|(_, on, chance)| {
+                    (on.is_empty() || on.contains(&sprite_index))
+                        && decoration_rng.gen::<f32>() < *chance
+                });
+                if let Some((index, _, _)) = decoration {
+                    tiles.push(Tile {
+                        point,
+                        sprite_order: TileLayer::Decoration.sprite_order(),
+                        sprite_index: *index,
+                        ..Default::default()
+                    });
+                }
+                if let Some(index) = fog.index {
+                    tiles.push(Tile {
+                        point,
+                        sprite_order: TileLayer::Overlay.sprite_order(),
+                        sprite_index: index,
+                        ..Default::default()
+                    });
+                }
+                tiles.push(Tile {
+                    point,
+                    sprite_index,
+                    ..Default::default()
+                });
+            }
+            for (pos, item_id) in content.items {
+                if !item_data.contains(&item_id) {
+                    if !unknown_items.contains(&item_id) {
+                        issues.push(AssetIssue::new(
+                            "data.config",
+                            Some("terrain.biomes"),
+                            format!("unknown item `{}`", item_id),
+                        ));
+                        unknown_items.push(item_id);
+                    }
+                    continue;
+                }
+                items::spawn_item(&mut commands, &mut item_events, &item_data, &item_id, pos);
+            }
+        }
+        validation::report(&issues);
+        map.insert_tiles(tiles).unwrap();
+
+        let chunks_x = map.width().unwrap() as i32;
+        let chunks_y = map.height().unwrap() as i32;
+        for chunk_y in 0..chunks_y {
+            for chunk_x in 0..chunks_x {
+                map.spawn_chunk((chunk_x - chunks_x / 2, chunk_y - chunks_y / 2))
+                    .unwrap();
+            }
+        }
+
+        let (min, max) = tile_range(&config.world);
+        commands.insert_resource(MapBounds {
+            min: Vec2::new(min.x as f32 * 32.0 - 16.0, min.y as f32 * 32.0 - 16.0),
+            max: Vec2::new(max.x as f32 * 32.0 + 16.0, max.y as f32 * 32.0 + 16.0),
+        });
+
+        state.set(AppState::Playing).unwrap();
+    }
+}
+
+// textures/ の画像が変更されたら atlas を作り直し, 配置は変えずにタイルの index だけ付け替える
+// アイテムは毎フレーム atlas から index を引くので付け替えは要らない
+fn rebuild_atlas(
+    mut ev_texture: EventReader<AssetEvent<Texture>>,
+    sprite_handles: Res<SpriteHandles>,
+    config: Res<GameConfig>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut fog: ResMut<Fog>,
+    mut query: Query<(&mut Tilemap,)>,
+) {
+    // any だと途中で止まって残りのイベントが次のフレームに持ち越されるので全部読む
+    let modified = ev_texture.iter().fold(false, |modified, ev| {
+        modified
+            || match ev {
+                AssetEvent::Modified { handle } => sprite_handles
+                    .textures_handles
+                    .iter()
+                    .any(|texture| texture.id == handle.id),
+                _ => false,
+            }
+    });
+    if !modified {
         return;
     }
-    for (mut map,) in query.iter_mut() {
-        let chunk_width = (map.width().unwrap() * map.chunk_width()) as i32;
-        let chunk_height = (map.height().unwrap() * map.chunk_height()) as i32;
 
-        let floor1: Handle<Texture> = asset_server.get_handle("textures/square-floor.png");
-        let floor2: Handle<Texture> = asset_server.get_handle("textures/square-floor_alt.png");
-        let texture_atlas = texture_atlases.get(map.texture_atlas()).unwrap();
-        let floor_index1 = texture_atlas.get_texture_index(&floor1).unwrap();
-        let floor_index2 = texture_atlas.get_texture_index(&floor2).unwrap();
-        println!("{}, {}", floor_index1, floor_index2);
+    let new_atlas = textures::build_atlas(&sprite_handles, &mut textures);
+    for (mut map,) in query.iter_mut() {
+        let old_atlas = match texture_atlases.get(map.texture_atlas()) {
+            Some(old_atlas) => old_atlas,
+            None => continue,
+        };
 
-        let mut rng = thread_rng();
+        // 古い index -> 新しい index
+        let mut remap = HashMap::new();
+        if let (Some(old_handles), Some(new_handles)) =
+            (&old_atlas.texture_handles, &new_atlas.texture_handles)
+        {
+            for (handle, old_index) in old_handles.iter() {
+                if let Some(new_index) = new_handles.get(handle) {
+                    remap.insert(*old_index, *new_index);
+                }
+            }
+        }
+        fog.index = fog.index.and_then(|index| remap.get(&index).copied());
 
+        let (min, max) = tile_range(&config.world);
         let mut tiles = Vec::new();
-        for y in 0..chunk_height {
-            for x in 0..chunk_width {
-                let i: bool = rng.gen();
-                let y = y - chunk_height / 2;
-                let x = x - chunk_width / 2;
-                let tile = Tile {
-                    point: (x, y),
-                    sprite_index: if i {floor_index1} else {floor_index2},
-                    ..Default::default()
-                };
-                tiles.push(tile);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let point = (x, y);
+                for sprite_order in 0..Z_LAYERS {
+                    if let Some(tile) = map.get_tile(point, sprite_order) {
+                        if let Some(new_index) = remap.get(&tile.index) {
+                            tiles.push(Tile {
+                                point,
+                                sprite_order,
+                                sprite_index: *new_index,
+                                tint: tile.color,
+                            });
+                        }
+                    }
+                }
             }
         }
+
         map.insert_tiles(tiles).unwrap();
+    }
+
+    // ハンドルはそのままにして中身だけ差し替える
+    if let Some(atlas) = texture_atlases.get_mut(&sprite_handles.atlas) {
+        *atlas = new_atlas;
+    }
+}
+
+fn apply_set_tile(
+    mut events: EventReader<SetTileEvent>,
+    mut changed_events: EventWriter<TileChangedEvent>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Tilemap>,
+) {
+    for ev in events.iter() {
+        query.iter_mut().for_each(|mut map| {
+            set_tile(
+                &mut map,
+                &texture_atlases,
+                &asset_server,
+                ev.pos,
+                ev.layer,
+                ev.texture.as_deref(),
+            )
+        });
+        changed_events.send(TileChangedEvent(ev.pos, ev.layer));
+    }
+}
 
-        map.spawn_chunk((-1, 0)).unwrap();
-        map.spawn_chunk((0, 0)).unwrap();
-        map.spawn_chunk((1, 0)).unwrap();
-        map.spawn_chunk((-1, 1)).unwrap();
-        map.spawn_chunk((0, 1)).unwrap();
-        map.spawn_chunk((1, 1)).unwrap();
-        map.spawn_chunk((-1, -1)).unwrap();
-        map.spawn_chunk((0, -1)).unwrap();
-        map.spawn_chunk((1, -1)).unwrap();
-
-        map_state.map_loaded = true;
+// プレイヤーの周りの霧を晴らす. 霧以外の overlay のタイルは残す
+fn reveal_fog(
+    config: Res<GameConfig>,
+    fog: Res<Fog>,
+    player_query: Query<&Position, (With<PlayerState>, Changed<Position>)>,
+    mut map_query: Query<&mut Tilemap>,
+) {
+    let (fog_index, radius) = match (fog.index, &config.world.fog) {
+        (Some(index), Some(fog_config)) => (index, fog_config.reveal_radius),
+        _ => return,
+    };
+    let sprite_order = TileLayer::Overlay.sprite_order();
+    for pos in player_query.iter() {
+        for mut map in map_query.iter_mut() {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if dx * dx + dy * dy > radius * radius {
+                        continue;
+                    }
+                    let point = (pos.x + dx, pos.y + dy);
+                    let fogged = map
+                        .get_tile(point, sprite_order)
+                        .map_or(false, |tile| tile.index == fog_index);
+                    if fogged {
+                        if let Err(err) = map.clear_tile(point, sprite_order) {
+                            warn!("failed to clear fog at {:?}: {:?}", point, err);
+                        }
+                    }
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+
+use super::{
+    config::{ConfigChanged, GameConfig, SimulationConfig},
+    loading::AppState,
+};
+
+// 固定間隔で回すステージ. 行動のタイマーやエージェントの判断はここに置く
+pub const SIMULATION: &str = "simulation";
+
+// 1 フレームで進める tick の上限. 超えた分は捨てる (処理落ちで追いつけなくなるのを防ぐ)
+pub const MAX_TICKS_PER_FRAME: u32 = 5;
+
+// Resource
+// ゲームの進行に使う時間. 普段は Time をそのまま写し, テストでは決まった幅で進める
+#[derive(Debug)]
+pub struct GameClock {
+    delta: Duration,
+    elapsed: Duration,
+    // Some なら実時間の代わりに毎フレームこの幅だけ進める
+    pub manual_step: Option<Duration>,
+    // true の間は tick を進めない (エディタで編集している間など)
+    pub paused: bool,
+    tick_duration: Duration,
+    // まだ tick に消化していない時間
+    accumulator: Duration,
+    frame_ticks: u32,
+    tick: u64,
+}
+
+impl Default for GameClock {
+    fn default() -> Self {
+        GameClock {
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            manual_step: None,
+            paused: false,
+            tick_duration: SimulationConfig::default().tick_duration(),
+            accumulator: Duration::ZERO,
+            frame_ticks: 0,
+            tick: 0,
+        }
+    }
+}
+
+impl GameClock {
+    // フレームの経過時間. 描画 (カメラ, アニメーション) 用
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn seconds_since_startup(&self) -> f64 {
+        self.elapsed.as_secs_f64()
+    }
+
+    // SIMULATION ステージの中ではこちらでタイマーを進める
+    pub fn tick_duration(&self) -> Duration {
+        self.tick_duration
+    }
+
+    // Playing になってから進めた tick 数
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    // tick の間も滑らかに動かすため, 最後の tick からの経過分だけ timer を先に進めた割合
+    pub fn smooth_percent(&self, timer: &Timer) -> f32 {
+        let duration = timer.duration().as_secs_f32();
+        if duration <= 0.0 {
+            return 1.0;
+        }
+        ((timer.elapsed() + self.accumulator).as_secs_f32() / duration).min(1.0)
+    }
+}
+
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // Time を更新する time_system は排他システムなので First の先頭で終わっている
+        app.init_resource::<GameClock>()
+            .add_system_to_stage(CoreStage::First, advance.system())
+            .add_system(apply_config.system().after("config"))
+            // 入力 (PreUpdate) の後, 描画用の位置計算 (Update) の前
+            .add_stage_after(
+                CoreStage::PreUpdate,
+                SIMULATION,
+                SystemStage::parallel().with_run_criteria(simulation_tick.system()),
+            );
+    }
+}
+
+fn advance(time: Res<Time>, mut clock: ResMut<GameClock>) {
+    let delta = clock.manual_step.unwrap_or_else(|| time.delta());
+    clock.delta = delta;
+    clock.elapsed += delta;
+    clock.accumulator += delta;
+    clock.frame_ticks = 0;
+}
+
+fn apply_config(
+    mut events: EventReader<ConfigChanged>,
+    config: Res<GameConfig>,
+    mut clock: ResMut<GameClock>,
+) {
+    for ev in events.iter() {
+        if ev.contains("simulation.tick_rate") {
+            clock.tick_duration = config.simulation.tick_duration();
+        }
+    }
+}
+
+// State の run criteria は driver の居るステージでしか使えないので, ここで Playing かを見る
+fn simulation_tick(state: Res<State<AppState>>, mut clock: ResMut<GameClock>) -> ShouldRun {
+    if *state.current() != AppState::Playing || clock.paused {
+        clock.accumulator = Duration::ZERO;
+        return ShouldRun::No;
+    }
+    if clock.accumulator < clock.tick_duration {
+        return ShouldRun::No;
+    }
+    if clock.frame_ticks >= MAX_TICKS_PER_FRAME {
+        clock.accumulator = Duration::ZERO;
+        return ShouldRun::No;
+    }
+    clock.accumulator -= clock.tick_duration;
+    clock.frame_ticks += 1;
+    clock.tick += 1;
+    ShouldRun::YesAndCheckAgain
+}
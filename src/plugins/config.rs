@@ -1,13 +1,556 @@
-use bevy::{prelude::*, reflect::TypeUuid};
+use std::{fmt::Debug, ops::Deref, time::Duration};
+
+use bevy::{asset::LoadState, prelude::*, reflect::TypeUuid};
 use bevy_asset_ron::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    loading::LoadingAssets,
+    validation::{self, AssetIssue},
+};
 
-#[derive(Deserialize, TypeUuid, Debug, Default)]
+#[derive(Deserialize, TypeUuid, Debug, Default, Clone, PartialEq)]
 #[uuid = "16170fe7-dcf0-e655-1422-d57a33356305"]
+#[serde(default, deny_unknown_fields)]
 pub struct GameConfigAsset {
     pub damage: f32,
     pub durability: f32,
     pub min_level: u8,
+    pub movement: MovementConfig,
+    pub items: ItemsConfig,
+    pub world: WorldConfig,
+    pub terrain: TerrainConfig,
+    pub camera: CameraConfig,
+    pub simulation: SimulationConfig,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MovementConfig {
+    // 1 回の歩行で進むマス数
+    pub speed: i32,
+    pub walk_duration_ms: u64,
+    pub pick_duration_ms: u64,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        MovementConfig {
+            speed: 1,
+            walk_duration_ms: 200,
+            pick_duration_ms: 500,
+        }
+    }
+}
+
+impl MovementConfig {
+    pub fn walk_duration(&self) -> Duration {
+        Duration::from_millis(self.walk_duration_ms)
+    }
+
+    pub fn pick_duration(&self) -> Duration {
+        Duration::from_millis(self.pick_duration_ms)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ItemsConfig {
+    // 持っているときの持ち主からの相対位置
+    pub carry_offset: (f32, f32),
+    // 拾う/置くアニメーションの足元側の位置
+    pub drop_offset: (f32, f32),
+}
+
+impl Default for ItemsConfig {
+    fn default() -> Self {
+        ItemsConfig {
+            carry_offset: (-12.0, -6.0),
+            drop_offset: (0.0, -28.0),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldConfig {
+    // チャンク数
+    pub chunks: (u32, u32),
+    // チャンクあたりのタイル数
+    pub chunk_size: (u32, u32),
+    pub player_y_offset: f32,
+    // 装飾の層に置くタイル (草, ひび割れ)
+    pub decorations: Vec<DecorationRule>,
+    // None なら霧を出さない
+    pub fog: Option<FogConfig>,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        WorldConfig {
+            chunks: (3, 3),
+            chunk_size: (64, 64),
+            player_y_offset: 28.0,
+            decorations: Vec::new(),
+            fog: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecorationRule {
+    pub texture: String,
+    // 1 タイルあたりに置かれる確率
+    pub chance: f32,
+    // この地面の画像の上にだけ置く. 空ならどの地面にも置く
+    pub on: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FogConfig {
+    pub texture: String,
+    // プレイヤーからこのマス数以内の霧を晴らす
+    pub reveal_radius: i32,
+}
+
+impl Default for FogConfig {
+    fn default() -> Self {
+        FogConfig {
+            texture: "textures/fog.png".to_string(),
+            reveal_radius: 6,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerrainConfig {
+    // 1 タイルあたりのノイズの周波数. 小さいほど biome が広がる
+    pub height_frequency: f64,
+    pub moisture_frequency: f64,
+    pub octaves: usize,
+    // 原点 (プレイヤーの出現位置) からこのマス数以内にはアイテムを置かない
+    pub spawn_clearance: i32,
+    // 上から順に見て最初に当てはまったもの. どれにも当てはまらなければ最後のもの
+    pub biomes: Vec<BiomeConfig>,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig {
+            height_frequency: 0.02,
+            moisture_frequency: 0.03,
+            octaves: 4,
+            spawn_clearance: 4,
+            biomes: vec![BiomeConfig::default()],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BiomeConfig {
+    pub name: String,
+    // 高さと湿り気 (どちらも 0..=1) がこの範囲 (両端を含む) に入るセル
+    pub height: (f64, f64),
+    pub moisture: (f64, f64),
+    // 地面の画像. セルごとにこの中から選ぶ
+    pub tiles: Vec<String>,
+    pub items: Vec<SpawnRule>,
+}
+
+impl Default for BiomeConfig {
+    fn default() -> Self {
+        BiomeConfig {
+            name: "meadow".to_string(),
+            height: (0.0, 1.0),
+            moisture: (0.0, 1.0),
+            tiles: vec![
+                "textures/square-floor.png".to_string(),
+                "textures/square-floor_alt.png".to_string(),
+            ],
+            items: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpawnRule {
+    pub item: String,
+    // 1 タイルあたりに置かれる確率
+    pub chance: f32,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    // 1 秒あたりに目標へ近づく割合
+    pub follow_speed: f32,
+    // この矩形の中ではプレイヤーが動いてもカメラは動かない (ワールド座標)
+    pub dead_zone: (f32, f32),
+    pub zoom: u32,
+    pub min_zoom: u32,
+    pub max_zoom: u32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            follow_speed: 8.0,
+            dead_zone: (64.0, 32.0),
+            zoom: 2,
+            min_zoom: 1,
+            max_zoom: 4,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    // 1 秒あたりの tick 数
+    pub tick_rate: u32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig { tick_rate: 60 }
+    }
+}
+
+impl SimulationConfig {
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.tick_rate as f64)
+    }
+}
+
+impl GameConfigAsset {
+    // フィールドを .. 無しで分解しておき, 足したフィールドをここに書き忘れたらコンパイルが通らないようにする
+    pub fn diff(&self, new: &GameConfigAsset) -> Vec<ConfigFieldChange> {
+        let mut changes = Vec::new();
+        let c = &mut changes;
+        let GameConfigAsset {
+            damage,
+            durability,
+            min_level,
+            movement,
+            items,
+            world,
+            terrain,
+            camera,
+            simulation,
+        } = self;
+        push_change(c, "damage", damage, &new.damage);
+        push_change(c, "durability", durability, &new.durability);
+        push_change(c, "min_level", min_level, &new.min_level);
+
+        let MovementConfig {
+            speed,
+            walk_duration_ms,
+            pick_duration_ms,
+        } = movement;
+        push_change(c, "movement.speed", speed, &new.movement.speed);
+        push_change(
+            c,
+            "movement.walk_duration_ms",
+            walk_duration_ms,
+            &new.movement.walk_duration_ms,
+        );
+        push_change(
+            c,
+            "movement.pick_duration_ms",
+            pick_duration_ms,
+            &new.movement.pick_duration_ms,
+        );
+
+        let ItemsConfig {
+            carry_offset,
+            drop_offset,
+        } = items;
+        push_change(
+            c,
+            "items.carry_offset",
+            carry_offset,
+            &new.items.carry_offset,
+        );
+        push_change(c, "items.drop_offset", drop_offset, &new.items.drop_offset);
+
+        let WorldConfig {
+            chunks,
+            chunk_size,
+            player_y_offset,
+            decorations,
+            fog,
+        } = world;
+        push_change(c, "world.chunks", chunks, &new.world.chunks);
+        push_change(c, "world.chunk_size", chunk_size, &new.world.chunk_size);
+        push_change(
+            c,
+            "world.player_y_offset",
+            player_y_offset,
+            &new.world.player_y_offset,
+        );
+        push_change(c, "world.decorations", decorations, &new.world.decorations);
+        push_change(c, "world.fog", fog, &new.world.fog);
+
+        let TerrainConfig {
+            height_frequency,
+            moisture_frequency,
+            octaves,
+            spawn_clearance,
+            biomes,
+        } = terrain;
+        push_change(
+            c,
+            "terrain.height_frequency",
+            height_frequency,
+            &new.terrain.height_frequency,
+        );
+        push_change(
+            c,
+            "terrain.moisture_frequency",
+            moisture_frequency,
+            &new.terrain.moisture_frequency,
+        );
+        push_change(c, "terrain.octaves", octaves, &new.terrain.octaves);
+        push_change(
+            c,
+            "terrain.spawn_clearance",
+            spawn_clearance,
+            &new.terrain.spawn_clearance,
+        );
+        push_change(c, "terrain.biomes", biomes, &new.terrain.biomes);
+
+        let CameraConfig {
+            follow_speed,
+            dead_zone,
+            zoom,
+            min_zoom,
+            max_zoom,
+        } = camera;
+        push_change(
+            c,
+            "camera.follow_speed",
+            follow_speed,
+            &new.camera.follow_speed,
+        );
+        push_change(c, "camera.dead_zone", dead_zone, &new.camera.dead_zone);
+        push_change(c, "camera.zoom", zoom, &new.camera.zoom);
+        push_change(c, "camera.min_zoom", min_zoom, &new.camera.min_zoom);
+        push_change(c, "camera.max_zoom", max_zoom, &new.camera.max_zoom);
+
+        let SimulationConfig { tick_rate } = simulation;
+        push_change(
+            c,
+            "simulation.tick_rate",
+            tick_rate,
+            &new.simulation.tick_rate,
+        );
+        changes
+    }
+
+    // 不正な値があればその理由を返す
+    pub fn validate(&self, file: &str) -> Vec<AssetIssue> {
+        let mut issues = Vec::new();
+        let mut check = |ok: bool, field: &str, reason: String| {
+            if !ok {
+                issues.push(AssetIssue::new(file, Some(field), reason));
+            }
+        };
+        let (movement, world, terrain, camera, simulation) = (
+            &self.movement,
+            &self.world,
+            &self.terrain,
+            &self.camera,
+            &self.simulation,
+        );
+        check(
+            movement.speed >= 1,
+            "movement.speed",
+            format!("must be >= 1 (got {})", movement.speed),
+        );
+        check(
+            movement.walk_duration_ms > 0,
+            "movement.walk_duration_ms",
+            "must be > 0".to_string(),
+        );
+        check(
+            movement.pick_duration_ms > 0,
+            "movement.pick_duration_ms",
+            "must be > 0".to_string(),
+        );
+        check(
+            world.chunks.0 > 0 && world.chunks.1 > 0,
+            "world.chunks",
+            format!("must be > 0 (got {:?})", world.chunks),
+        );
+        check(
+            world.chunk_size.0 > 0 && world.chunk_size.1 > 0,
+            "world.chunk_size",
+            format!("must be > 0 (got {:?})", world.chunk_size),
+        );
+        for rule in world.decorations.iter() {
+            check(
+                (0.0..=1.0).contains(&rule.chance),
+                "world.decorations",
+                format!(
+                    "chance of `{}` must be in 0..=1 (got {})",
+                    rule.texture, rule.chance
+                ),
+            );
+        }
+        if let Some(fog) = &world.fog {
+            check(
+                fog.reveal_radius >= 0,
+                "world.fog.reveal_radius",
+                format!("must not be negative (got {})", fog.reveal_radius),
+            );
+        }
+        check(
+            terrain.height_frequency > 0.0 && terrain.moisture_frequency > 0.0,
+            "terrain.height_frequency",
+            format!(
+                "frequencies must be > 0 (got {} and {})",
+                terrain.height_frequency, terrain.moisture_frequency
+            ),
+        );
+        check(
+            (1..=32).contains(&terrain.octaves),
+            "terrain.octaves",
+            format!("must be in 1..=32 (got {})", terrain.octaves),
+        );
+        check(
+            !terrain.biomes.is_empty(),
+            "terrain.biomes",
+            "must not be empty".to_string(),
+        );
+        for biome in terrain.biomes.iter() {
+            let in_unit = |(min, max): (f64, f64)| 0.0 <= min && min <= max && max <= 1.0;
+            check(
+                in_unit(biome.height) && in_unit(biome.moisture),
+                "terrain.biomes",
+                format!(
+                    "ranges of `{}` must be in 0..=1 (got height {:?}, moisture {:?})",
+                    biome.name, biome.height, biome.moisture
+                ),
+            );
+            check(
+                !biome.tiles.is_empty(),
+                "terrain.biomes",
+                format!("tiles of `{}` must not be empty", biome.name),
+            );
+            for rule in biome.items.iter() {
+                check(
+                    (0.0..=1.0).contains(&rule.chance),
+                    "terrain.biomes",
+                    format!(
+                        "chance of `{}` in `{}` must be in 0..=1 (got {})",
+                        rule.item, biome.name, rule.chance
+                    ),
+                );
+            }
+            // 順に足した値と比べて選ぶので, 合計が 1 を超えた分の規則は出ない
+            let total = biome.items.iter().map(|rule| rule.chance).sum::<f32>();
+            check(
+                total <= 1.0,
+                "terrain.biomes",
+                format!(
+                    "chances in `{}` must add up to at most 1 (got {})",
+                    biome.name, total
+                ),
+            );
+        }
+        check(
+            camera.follow_speed > 0.0,
+            "camera.follow_speed",
+            format!("must be > 0 (got {})", camera.follow_speed),
+        );
+        check(
+            camera.dead_zone.0 >= 0.0 && camera.dead_zone.1 >= 0.0,
+            "camera.dead_zone",
+            format!("must not be negative (got {:?})", camera.dead_zone),
+        );
+        check(
+            camera.min_zoom >= 1 && camera.min_zoom <= camera.max_zoom,
+            "camera.min_zoom",
+            format!(
+                "must be in 1..=max_zoom (got {}..={})",
+                camera.min_zoom, camera.max_zoom
+            ),
+        );
+        check(
+            camera.zoom >= camera.min_zoom && camera.zoom <= camera.max_zoom,
+            "camera.zoom",
+            format!(
+                "must be in {}..={} (got {})",
+                camera.min_zoom, camera.max_zoom, camera.zoom
+            ),
+        );
+        check(
+            simulation.tick_rate >= 1 && simulation.tick_rate <= 1000,
+            "simulation.tick_rate",
+            format!("must be in 1..=1000 (got {})", simulation.tick_rate),
+        );
+        issues
+    }
+}
+
+fn push_change<T: PartialEq + Debug>(
+    changes: &mut Vec<ConfigFieldChange>,
+    field: &'static str,
+    old: &T,
+    new: &T,
+) {
+    if old != new {
+        changes.push(ConfigFieldChange {
+            field,
+            old: format!("{:?}", old),
+            new: format!("{:?}", new),
+        });
+    }
+}
+
+// Resource
+// 読み込み済みの設定のスナップショット. 読み込み前はデフォルト値
+#[derive(Default, Clone, Debug)]
+pub struct GameConfig {
+    pub loaded: bool,
+    config: GameConfigAsset,
+}
+
+impl Deref for GameConfig {
+    type Target = GameConfigAsset;
+
+    fn deref(&self) -> &GameConfigAsset {
+        &self.config
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigFieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+// 読み込んだ値が直前の値と違うときに送られる. 初回読み込み (Created) は直前の値をデフォルト値として比べるので,
+// デフォルト値のままのフィールドは含まず, 全部デフォルト値なら送られない
+#[derive(Clone, Debug)]
+pub struct ConfigChanged {
+    pub changes: Vec<ConfigFieldChange>,
+}
+
+impl ConfigChanged {
+    // "camera" のようにセクション名を渡すとその中のどれかが変わったかを返す
+    pub fn contains(&self, field: &str) -> bool {
+        self.changes.iter().any(|change| {
+            change.field == field
+                || (change.field.starts_with(field) && change.field[field.len()..].starts_with('.'))
+        })
+    }
 }
 
 #[derive(Default, Clone)]
@@ -20,24 +563,82 @@ pub struct ConfigPlugin;
 impl Plugin for ConfigPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_plugin(
-            // load `*.item` files
+            // load `*.config` files
             RonAssetPlugin::<GameConfigAsset>::new(&["config"]),
         )
         .init_resource::<ConfigHandles>()
-        .add_startup_system(setup.system());
+        .init_resource::<GameConfig>()
+        .add_event::<ConfigChanged>()
+        .add_startup_system(setup.system())
+        .add_system(detect_change.system().label("config"));
     }
 }
 
-// TODO: 変更検知
-fn setup(mut config_handles: ResMut<ConfigHandles>, server: Res<AssetServer>) {
-    config_handles.handle = server.load("data.config");
+const CONFIG_FILE: &str = "data.config";
+
+fn setup(
+    mut config_handles: ResMut<ConfigHandles>,
+    mut loading_assets: ResMut<LoadingAssets>,
+    server: Res<AssetServer>,
+) {
+    config_handles.handle = server.load(CONFIG_FILE);
+    loading_assets.add(&config_handles.handle);
     server.watch_for_changes().unwrap();
 }
 
+fn detect_change(
+    mut ev_asset: EventReader<AssetEvent<GameConfigAsset>>,
+    mut event_writer: EventWriter<ConfigChanged>,
+    mut config: ResMut<GameConfig>,
+    handles: Res<ConfigHandles>,
+    asset_server: Res<AssetServer>,
+    assets: Res<Assets<GameConfigAsset>>,
+) {
+    // 読み込みに失敗した場合はデフォルト値で進める
+    if !config.loaded
+        && matches!(
+            asset_server.get_load_state(&handles.handle),
+            LoadState::Failed
+        )
+    {
+        validation::report(&[AssetIssue::new(
+            CONFIG_FILE,
+            None,
+            "failed to load (syntax error or unknown field), using defaults",
+        )]);
+        config.loaded = true;
+    }
+    for ev in ev_asset.iter() {
+        let handle = match ev {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != handles.handle {
+            continue;
+        }
+        if let Some(new) = assets.get(handle) {
+            let issues = new.validate(CONFIG_FILE);
+            if !issues.is_empty() {
+                validation::report(&issues);
+                // 不正な設定は反映せず直前の値 (初回はデフォルト値) を使い続ける
+                config.loaded = true;
+                continue;
+            }
+            let changes = config.config.diff(new);
+            config.config = new.clone();
+            config.loaded = true;
+            if !changes.is_empty() {
+                event_writer.send(ConfigChanged { changes });
+            }
+        }
+    }
+}
+
 // usage
-// fn print_config(assets: Res<Assets<GameConfigAsset>>, handles: Res<ConfigHandles>) {
-//     if let Some(s) = assets.get(&handles.handle) {
-//         println!("{:?}", s.damage)
-//         // 変更を検知しないと毎回呼ばれる
+// fn print_config(mut events: EventReader<ConfigChanged>, config: Res<GameConfig>) {
+//     for ev in events.iter() {
+//         if ev.contains("damage") {
+//             println!("{:?}", config.damage)
+//         }
 //     }
 // }
@@ -0,0 +1,586 @@
+use std::{fs, path::PathBuf};
+
+use bevy::{input::InputSystem, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::agents::player::{PlayerState, RightHand};
+
+use super::{
+    history::History,
+    items::{self, Item, ItemDataMap, Owner},
+    level::{LevelFile, LoadLevelEvent},
+    loading::AppState,
+    replay::ReplayMode,
+    ui::UiAssets,
+    world::{GameWorld, OldPosition, Position, WorldItemEvent, WorldSeed},
+};
+
+const CONSOLE_TOGGLE_KEY: KeyCode = KeyCode::Grave;
+// 画面に出す行数
+const VISIBLE_LINES: usize = 12;
+// これより古い出力と入力履歴は捨てる
+const MAX_LOG_LINES: usize = 200;
+const MAX_HISTORY: usize = 100;
+const DEFAULT_SAVE_FILE: &str = "save.ron";
+
+// (名前, 使い方). help と補完に使う
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "help"),
+    ("spawn", "spawn <item_id> <x> <y>"),
+    ("tp", "tp <x> <y>"),
+    ("give", "give <item_id>"),
+    ("clear", "clear <x> <y>"),
+    ("seed", "seed"),
+    ("save", "save [file]"),
+    ("load", "load [file]"),
+    ("level", "level <file>"),
+];
+
+// Resource
+#[derive(Default)]
+pub struct Console {
+    pub open: bool,
+    input: String,
+    log: Vec<String>,
+    // 実行した行. 上下キーで呼び出す
+    history: Vec<String>,
+    history_cursor: Option<usize>,
+    // 入力されたがまだ実行していない行. 実行は Playing の間だけ
+    pending: Vec<String>,
+}
+
+impl Console {
+    pub fn submit(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        self.print(format!("> {}", line));
+        if self.history.last().map(|last| last.as_str()) != Some(line) {
+            self.history.push(line.to_string());
+            if self.history.len() > MAX_HISTORY {
+                self.history.remove(0);
+            }
+        }
+        self.history_cursor = None;
+        self.pending.push(line.to_string());
+    }
+
+    pub fn print(&mut self, line: impl Into<String>) {
+        self.log.push(line.into());
+        if self.log.len() > MAX_LOG_LINES {
+            self.log.remove(0);
+        }
+    }
+
+    pub fn log(&self) -> &[String] {
+        &self.log
+    }
+
+    // 入力中の行
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    fn history_prev(&mut self) {
+        if self.history.is_empty() {
+            return;
+        }
+        let cursor = match self.history_cursor {
+            Some(cursor) => cursor.saturating_sub(1),
+            None => self.history.len() - 1,
+        };
+        self.history_cursor = Some(cursor);
+        self.input = self.history[cursor].clone();
+    }
+
+    fn history_next(&mut self) {
+        match self.history_cursor {
+            Some(cursor) if cursor + 1 < self.history.len() => {
+                self.history_cursor = Some(cursor + 1);
+                self.input = self.history[cursor + 1].clone();
+            }
+            Some(_) => {
+                self.history_cursor = None;
+                self.input.clear();
+            }
+            None => {}
+        }
+    }
+
+    // 最後の語を候補の共通部分まで伸ばす. 1 つに決まらなければ候補を出す
+    fn complete(&mut self, item_data: &ItemDataMap) {
+        let words = self.input.split(' ').collect::<Vec<_>>();
+        let word = words[words.len() - 1];
+        let mut candidates = match words.len() {
+            1 => COMMANDS.iter().map(|(name, _)| name.to_string()).collect(),
+            2 if matches!(words[0], "spawn" | "give") => item_data.ids().cloned().collect(),
+            _ => Vec::new(),
+        };
+        candidates.retain(|candidate| candidate.starts_with(word));
+        candidates.sort();
+
+        let completed = match candidates.as_slice() {
+            [] => return,
+            [only] => format!("{} ", only),
+            [first, rest @ ..] => {
+                // 共通部分の長さはバイト数. 文字の途中で切らないよう char_indices で数える
+                let common = rest.iter().fold(first.len(), |len, candidate| {
+                    first[..len]
+                        .char_indices()
+                        .zip(candidate.chars())
+                        .find(|((_, a), b)| a != b)
+                        .map_or(len.min(candidate.len()), |((i, _), _)| i)
+                });
+                if common == word.len() {
+                    self.print(candidates.join("  "));
+                    return;
+                }
+                first[..common].to_string()
+            }
+        };
+        let prefix_len = self.input.len() - word.len();
+        self.input.truncate(prefix_len);
+        self.input.push_str(&completed);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ConsoleCommand {
+    Help,
+    Spawn(String, Position),
+    Tp(Position),
+    Give(String),
+    Clear(Position),
+    Seed,
+    Save(PathBuf),
+    Load(PathBuf),
+    Level(PathBuf),
+}
+
+impl ConsoleCommand {
+    // リプレイに残らない変更
+    fn changes_world(&self) -> bool {
+        !matches!(
+            self,
+            ConsoleCommand::Help | ConsoleCommand::Seed | ConsoleCommand::Save(_)
+        )
+    }
+}
+
+fn parse_position(x: Option<&str>, y: Option<&str>) -> Result<Position, String> {
+    let parse = |value: Option<&str>| {
+        value
+            .ok_or_else(|| "missing coordinate".to_string())?
+            .parse::<i32>()
+            .map_err(|err| format!("invalid coordinate: {}", err))
+    };
+    Ok(Position {
+        x: parse(x)?,
+        y: parse(y)?,
+    })
+}
+
+fn parse(line: &str) -> Result<ConsoleCommand, String> {
+    let mut args = line.split_whitespace();
+    let name = args.next().unwrap_or_default();
+    let command = match name {
+        "help" => ConsoleCommand::Help,
+        "spawn" => {
+            let id = args.next().ok_or("missing item id")?;
+            ConsoleCommand::Spawn(id.to_string(), parse_position(args.next(), args.next())?)
+        }
+        "tp" => ConsoleCommand::Tp(parse_position(args.next(), args.next())?),
+        "give" => ConsoleCommand::Give(args.next().ok_or("missing item id")?.to_string()),
+        "clear" => ConsoleCommand::Clear(parse_position(args.next(), args.next())?),
+        "seed" => ConsoleCommand::Seed,
+        "save" => ConsoleCommand::Save(args.next().unwrap_or(DEFAULT_SAVE_FILE).into()),
+        "load" => ConsoleCommand::Load(args.next().unwrap_or(DEFAULT_SAVE_FILE).into()),
+        "level" => ConsoleCommand::Level(args.next().ok_or("missing file")?.into()),
+        _ => return Err(format!("unknown command `{}` (try `help`)", name)),
+    };
+    match args.next() {
+        Some(extra) => Err(format!("unexpected argument `{}`", extra)),
+        None => Ok(command),
+    }
+}
+
+// save / load の中身. アイテムは id で持つ (Entity は読み込み直すと変わる)
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SaveFile {
+    pub seed: u64,
+    pub player: (i32, i32),
+    pub right_hand: Option<String>,
+    pub items: Vec<(String, (i32, i32))>,
+}
+
+struct ConsoleNode;
+struct ConsoleText;
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Console>()
+            .add_startup_system(setup.system().after("ui_setup"))
+            // ゲームより先にキーを受け取り, 開いている間はゲームに渡さない
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                console_input
+                    .system()
+                    .label("console_input")
+                    .after(InputSystem)
+                    .before("latch_keys"),
+            )
+            .add_system(update_console.system())
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing).with_system(run_commands.system()),
+            );
+    }
+}
+
+fn setup(mut commands: Commands, ui_assets: Res<UiAssets>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(0.0),
+                    bottom: Val::Px(0.0),
+                    ..Default::default()
+                },
+                size: Size::new(Val::Percent(100.0), Val::Auto),
+                padding: Rect::all(Val::Px(6.0)),
+                ..Default::default()
+            },
+            material: ui_assets.panel.clone(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(ConsoleNode)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section("", ui_assets.text_style(14.0), Default::default()),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: true,
+                    },
+                    ..Default::default()
+                })
+                .insert(ConsoleNode)
+                .insert(ConsoleText);
+        });
+}
+
+fn console_input(
+    mut key_input: ResMut<Input<KeyCode>>,
+    mut chars: EventReader<ReceivedCharacter>,
+    mut console: ResMut<Console>,
+    item_data: Res<ItemDataMap>,
+) {
+    if key_input.just_pressed(CONSOLE_TOGGLE_KEY) {
+        console.open = !console.open;
+        // 開いたときの ` は入力に入れない
+        chars.iter().for_each(|_| {});
+    }
+    if !console.open {
+        chars.iter().for_each(|_| {});
+        return;
+    }
+
+    for ev in chars.iter() {
+        if !ev.char.is_control() && ev.char != '`' {
+            console.input.push(ev.char);
+        }
+    }
+    if key_input.just_pressed(KeyCode::Back) {
+        console.input.pop();
+    }
+    if key_input.just_pressed(KeyCode::Return) {
+        let line = std::mem::take(&mut console.input);
+        console.submit(&line);
+    }
+    if key_input.just_pressed(KeyCode::Up) {
+        console.history_prev();
+    }
+    if key_input.just_pressed(KeyCode::Down) {
+        console.history_next();
+    }
+    if key_input.just_pressed(KeyCode::Tab) {
+        console.complete(&item_data);
+    }
+
+    let pressed = key_input.get_pressed().copied().collect::<Vec<_>>();
+    pressed.into_iter().for_each(|key| key_input.reset(key));
+}
+
+// bevy 0.5 の Visible は子に伝播しないので全ノードを切り替える
+fn update_console(
+    console: Res<Console>,
+    mut node_query: Query<&mut Visible, With<ConsoleNode>>,
+    mut text_query: Query<&mut Text, With<ConsoleText>>,
+) {
+    if !console.is_changed() {
+        return;
+    }
+    node_query.iter_mut().for_each(|mut visible| {
+        if visible.is_visible != console.open {
+            visible.is_visible = console.open;
+        }
+    });
+    let start = console.log.len().saturating_sub(VISIBLE_LINES);
+    let mut value = console.log[start..].join("\n");
+    value.push_str(&format!("\n> {}_", console.input));
+    text_query.iter_mut().for_each(|mut text| {
+        text.sections[0].value = value.clone();
+    });
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn run_commands(
+    mut commands: Commands,
+    mut console: ResMut<Console>,
+    mut item_events: EventWriter<WorldItemEvent>,
+    mut level_events: EventWriter<LoadLevelEvent>,
+    item_data: Res<ItemDataMap>,
+    seed: Res<WorldSeed>,
+    mut history: ResMut<History>,
+    replay_mode: Option<Res<ReplayMode>>,
+    world_query: Query<&GameWorld>,
+    item_query: Query<(Entity, &Item, Option<&Position>)>,
+    mut player_query: Query<
+        (
+            Entity,
+            &PlayerState,
+            &mut Position,
+            &mut OldPosition,
+            &mut RightHand,
+        ),
+        Without<Item>,
+    >,
+) {
+    if console.pending.is_empty() {
+        return;
+    }
+
+    for line in std::mem::take(&mut console.pending) {
+        let command = match parse(&line) {
+            Ok(command) => command,
+            Err(err) => {
+                console.print(err);
+                continue;
+            }
+        };
+        let replaying = replay_mode.as_ref().map_or(false, |mode| mode.is_active());
+        if replaying && command.changes_world() {
+            console.print("can not change the world while recording or replaying");
+            continue;
+        }
+        let result: Result<String, String> = match command {
+            ConsoleCommand::Help => Ok(COMMANDS
+                .iter()
+                .map(|(_, usage)| *usage)
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ConsoleCommand::Spawn(id, pos) => {
+                if !item_data.contains(&id) {
+                    Err(format!("unknown item `{}`", id))
+                } else if item_at(&world_query, &pos).is_some() {
+                    Err(format!("({}, {}) is not empty", pos.x, pos.y))
+                } else {
+                    items::spawn_item(&mut commands, &mut item_events, &item_data, &id, pos);
+                    Ok(format!("spawned {} at ({}, {})", id, pos.x, pos.y))
+                }
+            }
+            ConsoleCommand::Tp(target) => match player_query.iter_mut().next() {
+                // 行動の途中で動かすと timer の終わりに元の行動の続きが走る
+                Some((_, state, _, _, _)) if *state != PlayerState::Idle => {
+                    Err("player is busy".to_string())
+                }
+                Some((_, _, mut pos, mut old_pos, _)) => {
+                    *pos = target;
+                    old_pos.x = target.x;
+                    old_pos.y = target.y;
+                    Ok(format!("teleported to ({}, {})", target.x, target.y))
+                }
+                None => Err("no player".to_string()),
+            },
+            ConsoleCommand::Give(id) => match player_query.iter_mut().next() {
+                Some((_, _, _, _, right_hand)) if right_hand.0.is_some() => {
+                    Err("hand is not empty".to_string())
+                }
+                Some((player, _, _, _, mut right_hand)) => {
+                    match items::spawn_item_entity(&mut commands, &item_data, &id) {
+                        Some(item) => {
+                            commands.entity(item).insert(Owner(player));
+                            right_hand.0 = Some(item);
+                            Ok(format!("gave {}", id))
+                        }
+                        None => Err(format!("unknown item `{}`", id)),
+                    }
+                }
+                None => Err("no player".to_string()),
+            },
+            ConsoleCommand::Clear(pos) => match item_at(&world_query, &pos) {
+                Some(item) => {
+                    commands.entity(item).despawn();
+                    item_events.send(WorldItemEvent::Remove(item, pos));
+                    Ok(format!("cleared ({}, {})", pos.x, pos.y))
+                }
+                None => Err(format!("({}, {}) is empty", pos.x, pos.y)),
+            },
+            ConsoleCommand::Seed => Ok(format!("seed: {}", seed.0)),
+            ConsoleCommand::Save(path) => {
+                let save = save_file(&seed, &item_query, &player_query);
+                ron::ser::to_string(&save)
+                    .map_err(|err| err.to_string())
+                    .and_then(|text| fs::write(&path, text).map_err(|err| err.to_string()))
+                    .map(|()| format!("saved to {}", path.display()))
+                    .map_err(|err| format!("failed to save {}: {}", path.display(), err))
+            }
+            ConsoleCommand::Load(_) | ConsoleCommand::Level(_)
+                if player_query
+                    .iter_mut()
+                    .any(|(_, state, _, _, _)| *state != PlayerState::Idle) =>
+            {
+                Err("player is busy".to_string())
+            }
+            ConsoleCommand::Load(path) => fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| {
+                    ron::de::from_bytes::<SaveFile>(&bytes).map_err(|err| err.to_string())
+                })
+                .map_err(|err| format!("failed to load {}: {}", path.display(), err))
+                .map(|save| {
+                    // 古いアイテムの Entity を指す履歴は使えなくなる
+                    history.clear();
+                    world_query.iter().for_each(|world| {
+                        world.item_map.iter().for_each(|(pos, item)| {
+                            item_events.send(WorldItemEvent::Remove(*item, *pos));
+                        });
+                    });
+                    item_query.iter().for_each(|(item, _, _)| {
+                        commands.entity(item).despawn();
+                    });
+                    let mut message = format!("loaded {}", path.display());
+                    if save.seed != seed.0 {
+                        message.push_str(&format!(
+                            " (saved with seed {}, the map is from seed {})",
+                            save.seed, seed.0
+                        ));
+                    }
+                    apply_save_file(
+                        &mut commands,
+                        &mut item_events,
+                        &item_data,
+                        &save,
+                        &mut player_query,
+                    );
+                    message
+                }),
+            ConsoleCommand::Level(path) => LevelFile::load(&path)
+                .map_err(|err| format!("failed to load {}: {}", path.display(), err))
+                .map(|level| {
+                    let mut message = format!("loaded level {}", path.display());
+                    let unknown = level
+                        .items
+                        .iter()
+                        .filter(|(_, item_id)| !item_data.contains(item_id))
+                        .count();
+                    if unknown > 0 {
+                        message.push_str(&format!(" ({} unknown items skipped)", unknown));
+                    }
+                    level_events.send(LoadLevelEvent(level));
+                    message
+                }),
+        };
+        match result {
+            Ok(message) => console.print(message),
+            Err(err) => console.print(err),
+        }
+    }
+}
+
+fn item_at(world_query: &Query<&GameWorld>, pos: &Position) -> Option<Entity> {
+    world_query
+        .iter()
+        .find_map(|world| world.item_map.get(pos).copied())
+}
+
+#[allow(clippy::type_complexity)]
+fn save_file(
+    seed: &WorldSeed,
+    item_query: &Query<(Entity, &Item, Option<&Position>)>,
+    player_query: &Query<
+        (
+            Entity,
+            &PlayerState,
+            &mut Position,
+            &mut OldPosition,
+            &mut RightHand,
+        ),
+        Without<Item>,
+    >,
+) -> SaveFile {
+    let mut save = SaveFile {
+        seed: seed.0,
+        ..Default::default()
+    };
+    if let Some((_, _, pos, _, right_hand)) = player_query.iter().next() {
+        save.player = (pos.x, pos.y);
+        save.right_hand = right_hand
+            .0
+            .and_then(|item| item_query.get(item).ok())
+            .map(|(_, item, _)| item.id().to_string());
+    }
+    save.items = item_query
+        .iter()
+        .filter_map(|(_, item, pos)| pos.map(|pos| (item.id().to_string(), (pos.x, pos.y))))
+        .collect();
+    save.items.sort();
+    save
+}
+
+#[allow(clippy::type_complexity)]
+fn apply_save_file(
+    commands: &mut Commands,
+    item_events: &mut EventWriter<WorldItemEvent>,
+    item_data: &ItemDataMap,
+    save: &SaveFile,
+    player_query: &mut Query<
+        (
+            Entity,
+            &PlayerState,
+            &mut Position,
+            &mut OldPosition,
+            &mut RightHand,
+        ),
+        Without<Item>,
+    >,
+) {
+    for (id, (x, y)) in save.items.iter() {
+        items::spawn_item(
+            commands,
+            item_events,
+            item_data,
+            id,
+            Position { x: *x, y: *y },
+        );
+    }
+    if let Some((player, _, mut pos, mut old_pos, mut right_hand)) = player_query.iter_mut().next()
+    {
+        let (x, y) = save.player;
+        *pos = Position { x, y };
+        old_pos.x = x;
+        old_pos.y = y;
+        right_hand.0 = save.right_hand.as_ref().and_then(|id| {
+            let item = items::spawn_item_entity(commands, item_data, id)?;
+            commands.entity(item).insert(Owner(player));
+            Some(item)
+        });
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use super::{items::Owner, loading::AppState, ui::UiAssets, world::TILE_SIZE};
+
+const DEBUG_TOGGLE_KEY: KeyCode = KeyCode::F3;
+
+// 行 (y) ごとの z の基準. ground とカメラ (CAMERA_Z) の間に収める
+const ROW_Z_ORIGIN: f32 = 500.0;
+// 1 行手前 (y が 1 小さい) になるごとに増える z
+const ROW_STEP: f32 = 1.0;
+// bevy_tilemap の z_layers はこの下に収まる
+const GROUND_OVERLAY_Z: f32 = 10.0;
+const DEBUG_LABEL_Z: f32 = 990.0;
+
+// Component
+// 描画の層. 同じ行の中では下のものほど手前に描かれる
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderLayer {
+    // tilemap. 行に関係なく一番奥
+    Ground,
+    // ホバーの枠など地面に貼り付くもの. 行に関係なく ground のすぐ上
+    GroundOverlay,
+    Item,
+    Agent,
+    // 持たれているアイテム. 持ち主のすぐ手前
+    Carried,
+}
+
+impl RenderLayer {
+    // 行の中での手前への寄せ. 次の行にかからないよう ROW_STEP より小さくする
+    fn row_offset(self) -> f32 {
+        match self {
+            RenderLayer::Ground | RenderLayer::GroundOverlay | RenderLayer::Item => 0.0,
+            RenderLayer::Agent => 0.3,
+            RenderLayer::Carried => 0.6,
+        }
+    }
+}
+
+// Component
+// row は足元 (画像の下端ではなく立っているセル) の行. 縦に長い画像でも足元で並べる
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Depth {
+    pub layer: RenderLayer,
+    pub row: f32,
+    // 行単位の補正 (アイテム定義の z_bias)
+    pub bias: f32,
+}
+
+impl Depth {
+    pub fn new(layer: RenderLayer) -> Self {
+        Depth {
+            layer,
+            row: 0.0,
+            bias: 0.0,
+        }
+    }
+
+    pub fn sort_z(&self) -> f32 {
+        sort_z(self.layer, self.row, self.bias)
+    }
+}
+
+pub fn sort_z(layer: RenderLayer, row: f32, bias: f32) -> f32 {
+    match layer {
+        RenderLayer::Ground => 0.0,
+        RenderLayer::GroundOverlay => GROUND_OVERLAY_Z,
+        _ => ROW_Z_ORIGIN - row * ROW_STEP + layer.row_offset() + bias,
+    }
+}
+
+// Resource
+#[derive(Default)]
+pub struct DepthDebug {
+    pub shown: bool,
+}
+
+struct SortKeyLabel(Entity);
+
+pub struct DepthPlugin;
+
+impl Plugin for DepthPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<DepthDebug>()
+            .add_system(
+                apply_depth
+                    .system()
+                    .label("depth")
+                    .after("position")
+                    .after("item_position"),
+            )
+            .add_system(toggle_debug.system().label("depth_debug_toggle"))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing).with_system(
+                    update_debug_labels
+                        .system()
+                        .after("depth")
+                        .after("depth_debug_toggle"),
+                ),
+            );
+    }
+}
+
+// 持たれているアイテムは持ち主の行を使い, 層だけ Carried にする
+fn apply_depth(mut query: Query<(&Depth, &mut Transform, Option<&Owner>)>) {
+    query.iter_mut().for_each(|(depth, mut transform, owner)| {
+        let layer = if owner.is_some() {
+            RenderLayer::Carried
+        } else {
+            depth.layer
+        };
+        let z = sort_z(layer, depth.row, depth.bias);
+        if transform.translation.z != z {
+            transform.translation.z = z;
+        }
+    });
+}
+
+fn toggle_debug(key_input: Res<Input<KeyCode>>, mut debug: ResMut<DepthDebug>) {
+    if key_input.just_pressed(DEBUG_TOGGLE_KEY) {
+        debug.shown = !debug.shown;
+    }
+}
+
+// 各エンティティの足元に z を表示する
+fn update_debug_labels(
+    mut commands: Commands,
+    debug: Res<DepthDebug>,
+    ui_assets: Res<UiAssets>,
+    sorted_query: Query<(Entity, &Depth, &Transform, Option<&Owner>), (Without<SortKeyLabel>,)>,
+    mut label_query: Query<(Entity, &SortKeyLabel, &mut Transform, &mut Text)>,
+) {
+    if !debug.shown {
+        label_query.iter_mut().for_each(|(label, _, _, _)| {
+            commands.entity(label).despawn();
+        });
+        return;
+    }
+
+    let mut labeled = HashSet::new();
+    label_query
+        .iter_mut()
+        .for_each(|(label, target, mut transform, mut text)| {
+            match sorted_query.get(target.0) {
+                Ok((_, depth, target_transform, owner)) => {
+                    transform.translation = Vec3::new(
+                        target_transform.translation.x,
+                        target_transform.translation.y - TILE_SIZE / 2.0,
+                        DEBUG_LABEL_Z,
+                    );
+                    let value = debug_text(depth, owner.is_some(), target_transform);
+                    if text.sections[0].value != value {
+                        text.sections[0].value = value;
+                    }
+                    labeled.insert(target.0);
+                }
+                // 対象が消えたラベルは消す
+                Err(_) => commands.entity(label).despawn(),
+            }
+        });
+
+    sorted_query
+        .iter()
+        .filter(|(entity, _, _, _)| !labeled.contains(entity))
+        .for_each(|(entity, depth, transform, owner)| {
+            commands
+                .spawn_bundle(Text2dBundle {
+                    text: Text::with_section(
+                        debug_text(depth, owner.is_some(), transform),
+                        ui_assets.text_style(8.0),
+                        TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
+                        },
+                    ),
+                    ..Default::default()
+                })
+                .insert(SortKeyLabel(entity));
+        });
+}
+
+fn debug_text(depth: &Depth, carried: bool, transform: &Transform) -> String {
+    let layer = if carried {
+        RenderLayer::Carried
+    } else {
+        depth.layer
+    };
+    format!("{:?} {:.2}", layer, transform.translation.z)
+}
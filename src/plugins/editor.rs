@@ -0,0 +1,571 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use bevy::{ecs::system::SystemParam, input::InputSystem, prelude::*};
+use bevy_tilemap::Tilemap;
+
+use crate::agents::player::PlayerState;
+
+use super::{
+    chunk::{self, TileChangedEvent, TileLayer},
+    clock::GameClock,
+    config::GameConfig,
+    items::{self, Item, ItemDataMap},
+    level::{LevelFile, LoadLevelEvent},
+    loading::AppState,
+    picking::HoveredCell,
+    replay::ReplayMode,
+    textures::SpriteHandles,
+    ui::UiAssets,
+    world::{GameWorld, Position, WorldItemEvent},
+};
+
+const EDITOR_TOGGLE_KEY: KeyCode = KeyCode::F5;
+const EXPORT_KEY: KeyCode = KeyCode::F6;
+const UNDO_KEY: KeyCode = KeyCode::U;
+const LAYER_KEY: KeyCode = KeyCode::L;
+const EXPORT_DIR: &str = "assets/levels";
+const EXPORT_NAME: &str = "untitled";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditorTool {
+    Paint,
+    Erase,
+    Fill,
+}
+
+impl Default for EditorTool {
+    fn default() -> Self {
+        EditorTool::Paint
+    }
+}
+
+// タイルは画像のパス, アイテムは id
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaletteEntry {
+    Tile(String),
+    Item(String),
+}
+
+// None は空のセル
+#[derive(Clone, Debug, PartialEq)]
+enum EditorChange {
+    Tile {
+        pos: Position,
+        layer: TileLayer,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    Item {
+        pos: Position,
+        before: Option<String>,
+        after: Option<String>,
+    },
+}
+
+// Resource
+#[derive(Default)]
+pub struct Editor {
+    pub active: bool,
+    pub tool: EditorTool,
+    // タイルを置く層
+    pub layer: TileLayer,
+    palette: Vec<PaletteEntry>,
+    selected: usize,
+    // マウスを押している間の変更. 離したら 1 回分の undo として積む
+    stroke: Vec<EditorChange>,
+    undo: Vec<Vec<EditorChange>>,
+    undo_requested: bool,
+    export_requested: bool,
+    message: String,
+}
+
+impl Editor {
+    pub fn selected(&self) -> Option<&PaletteEntry> {
+        self.palette.get(self.selected)
+    }
+
+    fn end_stroke(&mut self) {
+        if !self.stroke.is_empty() {
+            self.undo.push(std::mem::take(&mut self.stroke));
+        }
+    }
+}
+
+struct EditorNode;
+struct EditorText;
+
+// タイルとアイテムの読み書き
+#[derive(SystemParam)]
+struct LevelCells<'a> {
+    commands: Commands<'a>,
+    asset_server: Res<'a, AssetServer>,
+    texture_atlases: Res<'a, Assets<TextureAtlas>>,
+    item_data: Res<'a, ItemDataMap>,
+    tile_changed_events: EventWriter<'a, TileChangedEvent>,
+    item_events: EventWriter<'a, WorldItemEvent>,
+    tilemap_query: Query<'a, &'static mut Tilemap>,
+    world_query: Query<'a, &'static GameWorld>,
+    item_query: Query<'a, &'static Item>,
+    player_query: Query<'a, &'static Position, With<PlayerState>>,
+    // このフレームで置き換えたセル (Entity, id). item_map に入るのは PostUpdate なのでそれまではこちらを見る
+    placed: Local<'a, HashMap<Position, Option<(Entity, String)>>>,
+}
+
+impl<'a> LevelCells<'a> {
+    fn tile(&mut self, pos: Position, layer: TileLayer) -> Option<String> {
+        let asset_server = &self.asset_server;
+        let texture_atlases = &self.texture_atlases;
+        self.tilemap_query.iter_mut().find_map(|mut map| {
+            chunk::tile_texture(&mut map, texture_atlases, asset_server, pos, layer)
+        })
+    }
+
+    fn set_tile(&mut self, pos: Position, layer: TileLayer, texture: Option<&str>) {
+        let asset_server = &self.asset_server;
+        let texture_atlases = &self.texture_atlases;
+        self.tilemap_query.iter_mut().for_each(|mut map| {
+            chunk::set_tile(&mut map, texture_atlases, asset_server, pos, layer, texture)
+        });
+        self.tile_changed_events.send(TileChangedEvent(pos, layer));
+    }
+
+    fn item_entity(&self, pos: Position) -> Option<Entity> {
+        match self.placed.get(&pos) {
+            Some(placed) => placed.as_ref().map(|(entity, _)| *entity),
+            None => self
+                .world_query
+                .iter()
+                .find_map(|world| world.item_map.get(&pos).copied()),
+        }
+    }
+
+    fn item(&self, pos: Position) -> Option<String> {
+        if let Some(placed) = self.placed.get(&pos) {
+            return placed.as_ref().map(|(_, item_id)| item_id.clone());
+        }
+        let entity = self.item_entity(pos)?;
+        self.item_query
+            .get(entity)
+            .ok()
+            .map(|item| item.id().to_string())
+    }
+
+    fn set_item(&mut self, pos: Position, item_id: Option<&str>) {
+        if let Some(old) = self.item_entity(pos) {
+            self.commands.entity(old).despawn();
+            self.item_events.send(WorldItemEvent::Remove(old, pos));
+        }
+        let placed = item_id.and_then(|item_id| {
+            let entity = items::spawn_item_entity(&mut self.commands, &self.item_data, item_id)?;
+            self.commands.entity(entity).insert(pos);
+            self.item_events.send(WorldItemEvent::Add(entity, pos));
+            Some((entity, item_id.to_string()))
+        });
+        self.placed.insert(pos, placed);
+    }
+
+    fn player_at(&self, pos: Position) -> bool {
+        self.player_query.iter().any(|player| *player == pos)
+    }
+
+    fn get(&mut self, entry: &PaletteEntry, layer: TileLayer, pos: Position) -> Option<String> {
+        match entry {
+            PaletteEntry::Tile(_) => self.tile(pos, layer),
+            PaletteEntry::Item(_) => self.item(pos),
+        }
+    }
+
+    // 変わったときだけ変更を返す. プレイヤーの居るセルにはアイテムを置かない
+    fn set(
+        &mut self,
+        entry: &PaletteEntry,
+        layer: TileLayer,
+        pos: Position,
+        value: Option<&str>,
+    ) -> Option<EditorChange> {
+        let before = self.get(entry, layer, pos);
+        if before.as_deref() == value {
+            return None;
+        }
+        if matches!(entry, PaletteEntry::Item(_)) && value.is_some() && self.player_at(pos) {
+            return None;
+        }
+        let after = value.map(|value| value.to_string());
+        Some(match entry {
+            PaletteEntry::Tile(_) => {
+                self.set_tile(pos, layer, value);
+                EditorChange::Tile {
+                    pos,
+                    layer,
+                    before,
+                    after,
+                }
+            }
+            PaletteEntry::Item(_) => {
+                self.set_item(pos, value);
+                EditorChange::Item { pos, before, after }
+            }
+        })
+    }
+}
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Editor>()
+            .add_event::<LoadLevelEvent>()
+            .add_startup_system(setup.system().after("ui_setup"))
+            // エディタのキーはゲーム (プレイヤーの undo など) に渡さない
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                editor_keys
+                    .system()
+                    .after(InputSystem)
+                    .after("console_input")
+                    .before("latch_keys"),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(edit.system().label("editor").after("picking"))
+                    .with_system(update_panel.system().after("editor")),
+            );
+    }
+}
+
+fn setup(mut commands: Commands, ui_assets: Res<UiAssets>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    ..Default::default()
+                },
+                padding: Rect::all(Val::Px(6.0)),
+                ..Default::default()
+            },
+            material: ui_assets.panel.clone(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(EditorNode)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section("", ui_assets.text_style(14.0), Default::default()),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: true,
+                    },
+                    ..Default::default()
+                })
+                .insert(EditorNode)
+                .insert(EditorText);
+        });
+}
+
+// 開くたびに登録済みのタイルとアイテムから作り直す
+fn build_palette(
+    sprite_handles: &SpriteHandles,
+    texture_atlases: &Assets<TextureAtlas>,
+    asset_server: &AssetServer,
+    item_data: &ItemDataMap,
+) -> Vec<PaletteEntry> {
+    let mut tiles = sprite_handles
+        .textures_handles
+        .iter()
+        .filter(|handle| {
+            texture_atlases
+                .get(&sprite_handles.atlas)
+                .and_then(|atlas| atlas.get_texture_index(&handle.clone().typed::<Texture>()))
+                .is_some()
+        })
+        .filter_map(|handle| asset_server.get_handle_path(handle))
+        .map(|path| path.path().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    tiles.sort();
+    let mut item_ids = item_data.ids().cloned().collect::<Vec<_>>();
+    item_ids.sort();
+    tiles
+        .into_iter()
+        .map(PaletteEntry::Tile)
+        .chain(item_ids.into_iter().map(PaletteEntry::Item))
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn editor_keys(
+    mut key_input: ResMut<Input<KeyCode>>,
+    mut editor: ResMut<Editor>,
+    mut clock: ResMut<GameClock>,
+    state: Res<State<AppState>>,
+    sprite_handles: Res<SpriteHandles>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    asset_server: Res<AssetServer>,
+    item_data: Res<ItemDataMap>,
+    replay_mode: Option<Res<ReplayMode>>,
+) {
+    if key_input.just_pressed(EDITOR_TOGGLE_KEY) && *state.current() == AppState::Playing {
+        // リプレイは入力しか記録しないので, 編集した分がずれる
+        if replay_mode.map_or(false, |mode| mode.is_active()) {
+            info!("the editor is disabled while recording or replaying");
+            return;
+        }
+        editor.active = !editor.active;
+        // 編集している間はプレイヤーも世界も止める
+        clock.paused = editor.active;
+        if editor.active {
+            editor.palette =
+                build_palette(&sprite_handles, &texture_atlases, &asset_server, &item_data);
+            editor.selected = editor.selected.min(editor.palette.len().saturating_sub(1));
+        }
+    }
+    if !editor.active {
+        return;
+    }
+
+    let mut handled = Vec::new();
+    let mut pressed = |key: KeyCode| {
+        let pressed = key_input.just_pressed(key);
+        if key_input.pressed(key) {
+            handled.push(key);
+        }
+        pressed
+    };
+    if pressed(KeyCode::Key1) {
+        editor.tool = EditorTool::Paint;
+    }
+    if pressed(KeyCode::Key2) {
+        editor.tool = EditorTool::Erase;
+    }
+    if pressed(KeyCode::Key3) {
+        editor.tool = EditorTool::Fill;
+    }
+    let len = editor.palette.len().max(1);
+    if pressed(KeyCode::LBracket) {
+        editor.selected = (editor.selected + len - 1) % len;
+    }
+    if pressed(KeyCode::RBracket) {
+        editor.selected = (editor.selected + 1) % len;
+    }
+    if pressed(LAYER_KEY) {
+        let layers = TileLayer::ALL;
+        editor.layer = layers[(editor.layer.sprite_order() + 1) % layers.len()];
+    }
+    if pressed(UNDO_KEY) {
+        editor.undo_requested = true;
+    }
+    if pressed(EXPORT_KEY) {
+        editor.export_requested = true;
+    }
+    handled.into_iter().for_each(|key| key_input.reset(key));
+}
+
+// 既にあるファイルは上書きしない. untitled.level, untitled-2.level, ... の空いている名前を使う
+fn export_path() -> PathBuf {
+    (1..)
+        .map(|n| match n {
+            1 => Path::new(EXPORT_DIR).join(format!("{}.level", EXPORT_NAME)),
+            n => Path::new(EXPORT_DIR).join(format!("{}-{}.level", EXPORT_NAME, n)),
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+// マップと重なるセルだけ置き換える. 知らないアイテムは置かない
+fn import_level(
+    cells: &mut LevelCells,
+    level: &LevelFile,
+    min: Position,
+    max: Position,
+) -> Vec<EditorChange> {
+    let items = level
+        .items
+        .iter()
+        .filter(|(_, item_id)| cells.item_data.contains(item_id))
+        .map(|((x, y), item_id)| (Position { x: *x, y: *y }, item_id.clone()))
+        .collect::<HashMap<_, _>>();
+    // set は PaletteEntry の種類だけを見る
+    let tile = PaletteEntry::Tile(String::new());
+    let item = PaletteEntry::Item(String::new());
+    let (x0, y0) = level.origin;
+    let (x1, y1) = (x0 + level.size.0 as i32 - 1, y0 + level.size.1 as i32 - 1);
+    let mut changes = Vec::new();
+    for y in y0.max(min.y)..=y1.min(max.y) {
+        for x in x0.max(min.x)..=x1.min(max.x) {
+            let pos = Position { x, y };
+            for layer in [TileLayer::Ground, TileLayer::Decoration] {
+                changes.extend(cells.set(&tile, layer, pos, level.tile(pos, layer)));
+            }
+            let item_id = items.get(&pos).map(|item_id| item_id.as_str());
+            changes.extend(cells.set(&item, TileLayer::Ground, pos, item_id));
+        }
+    }
+    changes
+}
+
+fn edit(
+    mut editor: ResMut<Editor>,
+    mut level_events: EventReader<LoadLevelEvent>,
+    mouse_input: Res<Input<MouseButton>>,
+    hovered: Res<HoveredCell>,
+    config: Res<GameConfig>,
+    mut cells: LevelCells,
+) {
+    cells.placed.clear();
+    let (min, max) = chunk::tile_range(&config.world);
+    let in_range =
+        |pos: Position| pos.x >= min.x && pos.x <= max.x && pos.y >= min.y && pos.y <= max.y;
+
+    // エディタを閉じていても読み込む. 開けば U で戻せる
+    for LoadLevelEvent(level) in level_events.iter() {
+        editor.end_stroke();
+        let changes = import_level(&mut cells, level, min, max);
+        editor.message = format!("imported {} changes", changes.len());
+        editor.stroke = changes;
+        editor.end_stroke();
+    }
+
+    if !editor.active {
+        editor.end_stroke();
+        return;
+    }
+
+    if std::mem::take(&mut editor.undo_requested) {
+        editor.end_stroke();
+        if let Some(changes) = editor.undo.pop() {
+            for change in changes.iter().rev() {
+                match change {
+                    EditorChange::Tile {
+                        pos, layer, before, ..
+                    } => cells.set_tile(*pos, *layer, before.as_deref()),
+                    // 閉じている間にプレイヤーが移ってきたセルには戻さない
+                    EditorChange::Item { pos, before, .. }
+                        if before.is_some() && cells.player_at(*pos) => {}
+                    EditorChange::Item { pos, before, .. } => {
+                        cells.set_item(*pos, before.as_deref())
+                    }
+                }
+            }
+        }
+    }
+
+    if std::mem::take(&mut editor.export_requested) {
+        let mut level = LevelFile::new(min, max);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pos = Position { x, y };
+                for layer in [TileLayer::Ground, TileLayer::Decoration] {
+                    let tile = cells.tile(pos, layer);
+                    level.set_tile(pos, layer, tile.as_deref());
+                }
+                if let Some(item_id) = cells.item(pos) {
+                    level.items.push(((x, y), item_id));
+                }
+            }
+        }
+        let path = export_path();
+        editor.message = match level.save(&path) {
+            Ok(()) => format!("exported to {}", path.display()),
+            Err(err) => format!("failed to export: {}", err),
+        };
+    }
+
+    if mouse_input.just_released(MouseButton::Left) {
+        editor.end_stroke();
+    }
+    let entry = match editor.selected().cloned() {
+        Some(entry) => entry,
+        None => return,
+    };
+    let layer = editor.layer;
+    let pos = match hovered.pos {
+        Some(pos) if in_range(pos) => pos,
+        _ => return,
+    };
+    let value = match (&editor.tool, &entry) {
+        (EditorTool::Erase, _) => None,
+        (_, PaletteEntry::Tile(texture)) | (_, PaletteEntry::Item(texture)) => {
+            Some(texture.clone())
+        }
+    };
+
+    match editor.tool {
+        EditorTool::Paint | EditorTool::Erase if mouse_input.pressed(MouseButton::Left) => {
+            if let Some(change) = cells.set(&entry, layer, pos, value.as_deref()) {
+                editor.stroke.push(change);
+            }
+        }
+        // 押したセルと同じ中身で 4 方向につながる範囲を塗る
+        EditorTool::Fill if mouse_input.just_pressed(MouseButton::Left) => {
+            let target = cells.get(&entry, layer, pos);
+            if target == value {
+                return;
+            }
+            let mut visited = HashSet::new();
+            let mut queue = VecDeque::new();
+            visited.insert(pos);
+            queue.push_back(pos);
+            while let Some(pos) = queue.pop_front() {
+                if cells.get(&entry, layer, pos) != target {
+                    continue;
+                }
+                if let Some(change) = cells.set(&entry, layer, pos, value.as_deref()) {
+                    editor.stroke.push(change);
+                }
+                for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                    let next = Position {
+                        x: pos.x + dx,
+                        y: pos.y + dy,
+                    };
+                    if in_range(next) && visited.insert(next) {
+                        queue.push_back(next);
+                    }
+                }
+            }
+            editor.end_stroke();
+        }
+        _ => {}
+    }
+}
+
+// bevy 0.5 の Visible は子に伝播しないので全ノードを切り替える
+fn update_panel(
+    editor: Res<Editor>,
+    mut node_query: Query<&mut Visible, With<EditorNode>>,
+    mut text_query: Query<&mut Text, With<EditorText>>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+    node_query.iter_mut().for_each(|mut visible| {
+        if visible.is_visible != editor.active {
+            visible.is_visible = editor.active;
+        }
+    });
+    let selected = match editor.selected() {
+        Some(PaletteEntry::Tile(texture)) => format!("tile {} ({:?})", texture, editor.layer),
+        Some(PaletteEntry::Item(item_id)) => format!("item {}", item_id),
+        None => "(empty palette)".to_string(),
+    };
+    let value = format!(
+        "EDITOR  tool: {:?}\n[ ] {} ({}/{})\n1 paint  2 erase  3 fill  L layer\nU undo ({})  F6 export  F5 exit\n{}",
+        editor.tool,
+        selected,
+        editor.selected + 1,
+        editor.palette.len(),
+        editor.undo.len(),
+        editor.message,
+    );
+    text_query.iter_mut().for_each(|mut text| {
+        text.sections[0].value = value.clone();
+    });
+}
@@ -0,0 +1,303 @@
+use bevy::prelude::*;
+
+use super::{
+    chunk,
+    config::{ConfigChanged, GameConfig},
+    items::{Item, ItemAssets, ItemDataMap},
+    loading::AppState,
+    ui::UiAssets,
+    world::{GameWorld, OldPosition, Position, TILE_SIZE},
+};
+
+const DEBUG_TOGGLE_KEY: KeyCode = KeyCode::F4;
+
+// depth の sort key より手前, depth のラベル (990) より奥
+const CELL_Z: f32 = 970.0;
+const LINE_Z: f32 = 975.0;
+const ARROW_Z: f32 = 980.0;
+const LABEL_Z: f32 = 985.0;
+const GRID_LINE_WIDTH: f32 = 1.0;
+const CHUNK_LINE_WIDTH: f32 = 3.0;
+const ARROW_WIDTH: f32 = 3.0;
+const ARROW_HEAD_SIZE: f32 = 8.0;
+
+// Resource
+#[derive(Default)]
+pub struct GridDebug {
+    pub shown: bool,
+}
+
+// Resource
+#[derive(Default)]
+struct GridDebugMaterials {
+    grid: Handle<ColorMaterial>,
+    chunk: Handle<ColorMaterial>,
+    occupied: Handle<ColorMaterial>,
+    collision: Handle<ColorMaterial>,
+    // item_map が指しているのにそのセルに居ないアイテム
+    stale: Handle<ColorMaterial>,
+    arrow: Handle<ColorMaterial>,
+}
+
+// マップの大きさが変わったときだけ作り直すもの (格子とチャンクの境界)
+struct GridLine;
+// item_map かエージェントの位置が変わったときに作り直すもの
+struct GridMarker;
+
+pub struct GridDebugPlugin;
+
+impl Plugin for GridDebugPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<GridDebug>()
+            .init_resource::<GridDebugMaterials>()
+            .add_startup_system(setup.system())
+            .add_system(toggle.system().label("grid_debug_toggle"))
+            .add_system(update_lines.system().after("grid_debug_toggle"))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing).with_system(
+                    update_markers
+                        .system()
+                        .after("grid_debug_toggle")
+                        .after("position"),
+                ),
+            );
+    }
+}
+
+fn setup(
+    mut debug_materials: ResMut<GridDebugMaterials>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    *debug_materials = GridDebugMaterials {
+        grid: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.15).into()),
+        chunk: materials.add(Color::rgba(1.0, 0.9, 0.2, 0.6).into()),
+        occupied: materials.add(Color::rgba(0.2, 0.5, 1.0, 0.25).into()),
+        collision: materials.add(Color::rgba(1.0, 0.2, 0.2, 0.3).into()),
+        stale: materials.add(Color::rgba(1.0, 0.0, 1.0, 0.6).into()),
+        arrow: materials.add(Color::rgba(0.3, 1.0, 0.4, 0.8).into()),
+    };
+}
+
+fn toggle(key_input: Res<Input<KeyCode>>, mut debug: ResMut<GridDebug>) {
+    if key_input.just_pressed(DEBUG_TOGGLE_KEY) {
+        debug.shown = !debug.shown;
+    }
+}
+
+// セルの範囲 (両端を含む) の外枠のワールド座標
+fn cell_edges(min: Position, max: Position) -> (Vec2, Vec2) {
+    let half = Vec2::splat(TILE_SIZE / 2.0);
+    (min.to_world() - half, max.to_world() + half)
+}
+
+fn line_bundle(
+    from: Vec2,
+    to: Vec2,
+    width: f32,
+    z: f32,
+    material: Handle<ColorMaterial>,
+) -> SpriteBundle {
+    let delta = to - from;
+    let center = (from + to) / 2.0;
+    SpriteBundle {
+        sprite: Sprite::new(Vec2::new(delta.length(), width)),
+        material,
+        transform: Transform {
+            translation: center.extend(z),
+            rotation: Quat::from_rotation_z(delta.y.atan2(delta.x)),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn rect_bundle(center: Vec2, size: Vec2, z: f32, material: Handle<ColorMaterial>) -> SpriteBundle {
+    SpriteBundle {
+        sprite: Sprite::new(size),
+        material,
+        transform: Transform::from_translation(center.extend(z)),
+        ..Default::default()
+    }
+}
+
+fn update_lines(
+    mut commands: Commands,
+    mut config_events: EventReader<ConfigChanged>,
+    debug: Res<GridDebug>,
+    config: Res<GameConfig>,
+    materials: Res<GridDebugMaterials>,
+    query: Query<Entity, With<GridLine>>,
+) {
+    let resized = config_events
+        .iter()
+        .any(|ev| ev.contains("world.chunks") || ev.contains("world.chunk_size"));
+    if !debug.is_changed() && !(resized && debug.shown) {
+        return;
+    }
+    query
+        .iter()
+        .for_each(|entity| commands.entity(entity).despawn());
+    if !debug.shown {
+        return;
+    }
+
+    let (min, max) = chunk::tile_range(&config.world);
+    let (min, max) = cell_edges(min, max);
+    let mut x = min.x;
+    while x <= max.x {
+        commands
+            .spawn_bundle(line_bundle(
+                Vec2::new(x, min.y),
+                Vec2::new(x, max.y),
+                GRID_LINE_WIDTH,
+                LINE_Z,
+                materials.grid.clone(),
+            ))
+            .insert(GridLine);
+        x += TILE_SIZE;
+    }
+    let mut y = min.y;
+    while y <= max.y {
+        commands
+            .spawn_bundle(line_bundle(
+                Vec2::new(min.x, y),
+                Vec2::new(max.x, y),
+                GRID_LINE_WIDTH,
+                LINE_Z,
+                materials.grid.clone(),
+            ))
+            .insert(GridLine);
+        y += TILE_SIZE;
+    }
+
+    for (chunk_min, chunk_max) in chunk::chunk_ranges(&config.world) {
+        let (min, max) = cell_edges(chunk_min, chunk_max);
+        let corners = [
+            Vec2::new(min.x, min.y),
+            Vec2::new(max.x, min.y),
+            Vec2::new(max.x, max.y),
+            Vec2::new(min.x, max.y),
+        ];
+        for i in 0..corners.len() {
+            commands
+                .spawn_bundle(line_bundle(
+                    corners[i],
+                    corners[(i + 1) % corners.len()],
+                    CHUNK_LINE_WIDTH,
+                    LINE_Z,
+                    materials.chunk.clone(),
+                ))
+                .insert(GridLine);
+        }
+    }
+}
+
+// item_map の中身と動いているエージェントを描く. 変わったフレームだけ作り直す
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_markers(
+    mut commands: Commands,
+    debug: Res<GridDebug>,
+    materials: Res<GridDebugMaterials>,
+    ui_assets: Res<UiAssets>,
+    item_data: Res<ItemDataMap>,
+    item_assets: Res<Assets<ItemAssets>>,
+    marker_query: Query<Entity, With<GridMarker>>,
+    world_query: Query<&GameWorld>,
+    item_query: Query<(&Item, Option<&Position>)>,
+    agent_query: Query<(Entity, &Position, &OldPosition)>,
+    changed_world_query: Query<(), Changed<GameWorld>>,
+    moved_query: Query<(), Or<(Changed<Position>, Changed<OldPosition>)>>,
+) {
+    let changed = debug.is_changed()
+        || item_data.is_changed()
+        || changed_world_query.iter().next().is_some()
+        || moved_query.iter().next().is_some();
+    if !changed {
+        return;
+    }
+    marker_query
+        .iter()
+        .for_each(|entity| commands.entity(entity).despawn());
+    if !debug.shown {
+        return;
+    }
+
+    let cell = Vec2::splat(TILE_SIZE);
+    let label_style = ui_assets.text_style(8.0);
+    let alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    };
+    let label = |commands: &mut Commands, text: String, at: Vec2| {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(text, label_style.clone(), alignment),
+                transform: Transform::from_translation(at.extend(LABEL_Z)),
+                ..Default::default()
+            })
+            .insert(GridMarker);
+    };
+
+    for world in world_query.iter() {
+        for (pos, entity) in world.item_map.iter() {
+            let center = pos.to_world();
+            let (material, text) = match item_query.get(*entity) {
+                // 置かれている場所が違う (あるいは持たれている) なら item_map が古い
+                Ok((item, item_pos)) if item_pos != Some(pos) => (
+                    materials.stale.clone(),
+                    format!("{} {}?", entity.id(), item.id()),
+                ),
+                Ok((item, _)) => {
+                    let collision = item_data
+                        .get(item.id(), &item_assets)
+                        .map_or(false, |assets| assets.collision);
+                    let material = if collision {
+                        materials.collision.clone()
+                    } else {
+                        materials.occupied.clone()
+                    };
+                    (material, format!("{} {}", entity.id(), item.id()))
+                }
+                Err(_) => (materials.stale.clone(), format!("{} (gone)", entity.id())),
+            };
+            commands
+                .spawn_bundle(rect_bundle(center, cell, CELL_Z, material))
+                .insert(GridMarker);
+            label(&mut commands, text, center);
+        }
+    }
+
+    for (entity, pos, old_pos) in agent_query.iter() {
+        let to = pos.to_world();
+        label(
+            &mut commands,
+            format!("{}", entity.id()),
+            to + Vec2::new(0.0, TILE_SIZE / 2.0),
+        );
+        if (old_pos.x, old_pos.y) == (pos.x, pos.y) {
+            continue;
+        }
+        let from = Position {
+            x: old_pos.x,
+            y: old_pos.y,
+        }
+        .to_world();
+        commands
+            .spawn_bundle(line_bundle(
+                from,
+                to,
+                ARROW_WIDTH,
+                ARROW_Z,
+                materials.arrow.clone(),
+            ))
+            .insert(GridMarker);
+        commands
+            .spawn_bundle(rect_bundle(
+                to,
+                Vec2::splat(ARROW_HEAD_SIZE),
+                ARROW_Z,
+                materials.arrow.clone(),
+            ))
+            .insert(GridMarker);
+    }
+}
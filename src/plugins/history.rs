@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::agents::player::{InputCommands, PlayerState, RightHand};
+
+use super::{
+    clock::SIMULATION,
+    items::{Item, Owner},
+    world::{GameWorld, OldPosition, Position, WorldItemEvent},
+};
+
+// これより古い行動は捨てる
+const MAX_UNDO_STEPS: usize = 1000;
+
+// 戻せる変更. holder はアイテムを持っていた (持った) プレイヤー
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorldCommand {
+    // holder の手から pos に置いた
+    AddItem {
+        item: Entity,
+        pos: Position,
+        holder: Entity,
+    },
+    // pos から holder の手に取った
+    RemoveItem {
+        item: Entity,
+        pos: Position,
+        holder: Entity,
+    },
+    MovePlayer {
+        player: Entity,
+        from: Position,
+        to: Position,
+    },
+}
+
+// Resource
+// 1 要素が 1 行動 (歩く, 拾う, 置く, 持ち替える) で起きた変更
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Vec<WorldCommand>>,
+    redo: Vec<Vec<WorldCommand>>,
+    // 実行中の行動の変更
+    current: Vec<WorldCommand>,
+    // undo/redo 自身が送ったイベント. 行動としては記録しない
+    replayed: Vec<WorldItemEvent>,
+}
+
+impl History {
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.current.clear();
+    }
+
+    fn close_action(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        self.undo.push(std::mem::take(&mut self.current));
+        if self.undo.len() > MAX_UNDO_STEPS {
+            self.undo.remove(0);
+        }
+        // 新しく行動したらやり直しの先は無くなる
+        self.redo.clear();
+    }
+}
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<History>().add_system_set_to_stage(
+            SIMULATION,
+            SystemSet::new()
+                .with_system(
+                    undo_redo
+                        .system()
+                        .label("history")
+                        .after("read_input")
+                        .before("end_action_process")
+                        .before("input"),
+                )
+                .with_system(
+                    log_actions
+                        .system()
+                        .label("history_log")
+                        .after("start_action_process"),
+                ),
+        );
+    }
+}
+
+// 始めたばかりの行動は start_action_process で timer が巻き戻されている
+fn just_started(timer: &Timer) -> bool {
+    !timer.finished() && timer.elapsed().as_nanos() == 0
+}
+
+// 同じ tick で前の行動の終わり (AddItem) と次の行動の始め (RemoveItem, 移動) が来るので,
+// 終わりの分を今の行動に足して閉じてから次の行動を始める
+fn log_actions(
+    mut history: ResMut<History>,
+    mut item_events: EventReader<WorldItemEvent>,
+    owner_query: Query<&Owner>,
+    player_query: Query<(Entity, &PlayerState, &Timer, &Position, &OldPosition)>,
+) {
+    let mut removed = Vec::new();
+    for ev in item_events.iter() {
+        if let Some(i) = history.replayed.iter().position(|replayed| replayed == ev) {
+            history.replayed.remove(i);
+            continue;
+        }
+        match *ev {
+            // 持ち主の居ないアイテム (生成や定義の再読み込み) はプレイヤーの行動ではない
+            WorldItemEvent::Add(item, pos) => {
+                if let Ok(owner) = owner_query.get(item) {
+                    history.current.push(WorldCommand::AddItem {
+                        item,
+                        pos,
+                        holder: owner.0,
+                    });
+                }
+            }
+            WorldItemEvent::Remove(item, pos) => removed.push((item, pos)),
+        }
+    }
+
+    let action_ended = player_query
+        .iter()
+        .any(|(_, state, timer, _, _)| *state == PlayerState::Idle || just_started(timer));
+    if action_ended {
+        history.close_action();
+    }
+
+    for (item, pos) in removed {
+        let holder = player_query
+            .iter()
+            .find(|(_, state, _, _, _)| match state {
+                PlayerState::Pick(picked) | PlayerState::PickAndDrop(picked) => *picked == item,
+                _ => false,
+            })
+            .map(|(player, _, _, _, _)| player);
+        if let Some(holder) = holder {
+            history
+                .current
+                .push(WorldCommand::RemoveItem { item, pos, holder });
+        }
+    }
+    player_query
+        .iter()
+        .filter(|(_, state, timer, _, _)| {
+            matches!(state, PlayerState::Walk(_)) && just_started(timer)
+        })
+        .for_each(|(player, _, _, pos, old_pos)| {
+            history.current.push(WorldCommand::MovePlayer {
+                player,
+                from: Position {
+                    x: old_pos.x,
+                    y: old_pos.y,
+                },
+                to: *pos,
+            });
+        });
+}
+
+// undo/redo で当てる 1 つ分の変更
+#[derive(Clone, Copy)]
+enum Step {
+    // holder の手から pos に置く
+    ToGround {
+        item: Entity,
+        pos: Position,
+        holder: Entity,
+    },
+    // pos から holder の手に取る
+    ToHand {
+        item: Entity,
+        pos: Position,
+        holder: Entity,
+    },
+    MovePlayer {
+        player: Entity,
+        to: Position,
+    },
+}
+
+// 戻すときは逆順に, 逆向きの変更を当てる
+fn steps(action: &[WorldCommand], undo: bool) -> Vec<Step> {
+    let step = |command: &WorldCommand| match *command {
+        WorldCommand::AddItem { item, pos, holder } if undo => Step::ToHand { item, pos, holder },
+        WorldCommand::AddItem { item, pos, holder } => Step::ToGround { item, pos, holder },
+        WorldCommand::RemoveItem { item, pos, holder } if undo => {
+            Step::ToGround { item, pos, holder }
+        }
+        WorldCommand::RemoveItem { item, pos, holder } => Step::ToHand { item, pos, holder },
+        WorldCommand::MovePlayer { player, from, to } => Step::MovePlayer {
+            player,
+            to: if undo { from } else { to },
+        },
+    };
+    if undo {
+        action.iter().rev().map(step).collect()
+    } else {
+        action.iter().map(step).collect()
+    }
+}
+
+// 行動の後に別のアイテムやプレイヤーが入ったセルに戻そうとしていればそのセルを返す.
+// 途中の手順で空いたり埋まったりする分も数える
+fn blocked_cell(
+    steps: &[Step],
+    item_map: &HashMap<Position, Entity>,
+    mut players: HashMap<Entity, Position>,
+) -> Option<Position> {
+    let mut cells: HashMap<Position, Option<Entity>> = HashMap::new();
+    for step in steps {
+        match *step {
+            Step::ToGround { item, pos, .. } => {
+                let occupant = match cells.get(&pos) {
+                    Some(occupant) => *occupant,
+                    None => item_map.get(&pos).copied(),
+                };
+                if occupant.map_or(false, |occupant| occupant != item) {
+                    return Some(pos);
+                }
+                cells.insert(pos, Some(item));
+            }
+            Step::ToHand { item, pos, .. } => {
+                let occupant = match cells.get(&pos) {
+                    Some(occupant) => *occupant,
+                    None => item_map.get(&pos).copied(),
+                };
+                if occupant == Some(item) {
+                    cells.insert(pos, None);
+                }
+            }
+            Step::MovePlayer { player, to } => {
+                if players
+                    .iter()
+                    .any(|(other, pos)| *other != player && *pos == to)
+                {
+                    return Some(to);
+                }
+                players.insert(player, to);
+            }
+        }
+    }
+    None
+}
+
+// 行動中は戻さない. 戻せない手順があれば何も変えずに履歴へ戻す
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn undo_redo(
+    mut commands: Commands,
+    input_commands: Res<InputCommands>,
+    mut history: ResMut<History>,
+    mut item_events: EventWriter<WorldItemEvent>,
+    item_query: Query<(), With<Item>>,
+    world_query: Query<&GameWorld>,
+    state_query: Query<(&PlayerState, &Timer)>,
+    mut player_query: Query<
+        (Entity, &mut Position, &mut OldPosition, &mut RightHand),
+        With<PlayerState>,
+    >,
+) {
+    if !input_commands.undo && !input_commands.redo {
+        return;
+    }
+    let busy = state_query
+        .iter()
+        .any(|(state, timer)| *state != PlayerState::Idle || !timer.finished());
+    if busy {
+        return;
+    }
+
+    let undo = input_commands.undo;
+    let action = match if undo {
+        history.undo.pop()
+    } else {
+        history.redo.pop()
+    } {
+        Some(action) => action,
+        None => return,
+    };
+
+    // 定義の再読み込みで消えたアイテムは飛ばす
+    let steps = steps(&action, undo)
+        .into_iter()
+        .filter(|step| match *step {
+            Step::ToGround { item, .. } | Step::ToHand { item, .. } => item_query.get(item).is_ok(),
+            Step::MovePlayer { .. } => true,
+        })
+        .collect::<Vec<_>>();
+    let players = player_query
+        .iter()
+        .map(|(player, pos, _, _)| (player, *pos))
+        .collect();
+    let blocked = world_query
+        .iter()
+        .find_map(|world| blocked_cell(&steps, &world.item_map, players.clone()));
+    if let Some(pos) = blocked {
+        info!(
+            "can not {}: ({}, {}) is occupied",
+            if undo { "undo" } else { "redo" },
+            pos.x,
+            pos.y
+        );
+        if undo {
+            history.undo.push(action);
+        } else {
+            history.redo.push(action);
+        }
+        return;
+    }
+
+    let mut sent = Vec::new();
+    for step in steps {
+        match step {
+            Step::ToGround { item, pos, holder } => {
+                commands.entity(item).remove::<Owner>().insert(pos);
+                sent.push(WorldItemEvent::Add(item, pos));
+                if let Ok((_, _, _, mut right_hand)) = player_query.get_mut(holder) {
+                    if right_hand.0 == Some(item) {
+                        right_hand.0 = None;
+                    }
+                }
+            }
+            Step::ToHand { item, pos, holder } => {
+                commands
+                    .entity(item)
+                    .remove::<Position>()
+                    .insert(Owner(holder));
+                sent.push(WorldItemEvent::Remove(item, pos));
+                if let Ok((_, _, _, mut right_hand)) = player_query.get_mut(holder) {
+                    right_hand.0 = Some(item);
+                }
+            }
+            Step::MovePlayer { player, to } => {
+                if let Ok((_, mut pos, mut old_pos, _)) = player_query.get_mut(player) {
+                    *pos = to;
+                    old_pos.x = to.x;
+                    old_pos.y = to.y;
+                }
+            }
+        }
+    }
+
+    history.replayed.extend(sent.iter().copied());
+    item_events.send_batch(sent.into_iter());
+
+    if undo {
+        history.redo.push(action);
+    } else {
+        history.undo.push(action);
+    }
+}
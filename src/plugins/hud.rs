@@ -0,0 +1,193 @@
+use bevy::prelude::*;
+
+use crate::agents::player::{PlayerState, RightHand, Vitals};
+
+use super::{
+    items::{Item, ItemDataMap},
+    loading::AppState,
+    ui::UiAssets,
+    world::Position,
+};
+
+const HUD_TOGGLE_KEY: KeyCode = KeyCode::F1;
+const BAR_WIDTH: f32 = 100.0;
+const BAR_HEIGHT: f32 = 8.0;
+
+// Resource
+pub struct HudState {
+    pub shown: bool,
+}
+
+impl Default for HudState {
+    fn default() -> Self {
+        HudState { shown: true }
+    }
+}
+
+struct HudNode;
+struct HudIcon;
+struct HudText;
+
+// Component
+// Vitals の割合で幅を変えるバー
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HudBar {
+    Health,
+    Hunger,
+}
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<HudState>()
+            .add_startup_system(setup.system().after("ui_setup"))
+            .add_system(toggle.system().label("hud_toggle"))
+            .add_system(update_visibility.system().after("hud_toggle"))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing).with_system(update_hud.system()),
+            );
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    ui_assets: Res<UiAssets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let bar_background = materials.add(Color::rgb(0.15, 0.15, 0.15).into());
+    let health = materials.add(Color::rgb(0.8, 0.2, 0.2).into());
+    let hunger = materials.add(Color::rgb(0.85, 0.6, 0.2).into());
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    ..Default::default()
+                },
+                // bevy の UI は y 上向きなので上から並べるには ColumnReverse
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::FlexStart,
+                padding: Rect::all(Val::Px(6.0)),
+                ..Default::default()
+            },
+            material: ui_assets.panel.clone(),
+            ..Default::default()
+        })
+        .insert(HudNode)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(ImageBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(32.0), Val::Px(32.0)),
+                        margin: Rect::all(Val::Px(2.0)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(HudNode)
+                .insert(HudIcon);
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section("", ui_assets.text_style(14.0), Default::default()),
+                    ..Default::default()
+                })
+                .insert(HudNode)
+                .insert(HudText);
+            for (bar, material) in [(HudBar::Health, health), (HudBar::Hunger, hunger)] {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(BAR_WIDTH), Val::Px(BAR_HEIGHT)),
+                            margin: Rect::all(Val::Px(2.0)),
+                            ..Default::default()
+                        },
+                        material: bar_background.clone(),
+                        ..Default::default()
+                    })
+                    .insert(HudNode)
+                    .with_children(|parent| {
+                        parent
+                            .spawn_bundle(NodeBundle {
+                                style: Style {
+                                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                                    ..Default::default()
+                                },
+                                material,
+                                ..Default::default()
+                            })
+                            .insert(HudNode)
+                            .insert(bar);
+                    });
+            }
+        });
+}
+
+fn toggle(key_input: Res<Input<KeyCode>>, mut hud: ResMut<HudState>) {
+    if key_input.just_pressed(HUD_TOGGLE_KEY) {
+        hud.shown = !hud.shown;
+    }
+}
+
+// bevy 0.5 の Visible は子に伝播しないので全ノードを切り替える
+fn update_visibility(
+    hud: Res<HudState>,
+    state: Res<State<AppState>>,
+    player_query: Query<(&RightHand,)>,
+    mut query: Query<(&mut Visible, Option<&HudIcon>), (With<HudNode>,)>,
+) {
+    let holding = player_query
+        .iter()
+        .any(|(right_hand,)| right_hand.0.is_some());
+    query.iter_mut().for_each(|(mut visible, icon)| {
+        let shown =
+            hud.shown && *state.current() == AppState::Playing && (icon.is_none() || holding);
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+    });
+}
+
+fn update_hud(
+    item_data: Res<ItemDataMap>,
+    player_query: Query<(&Position, &PlayerState, &RightHand, &Vitals)>,
+    item_query: Query<(&Item,)>,
+    mut icon_query: Query<(&mut Handle<ColorMaterial>,), (With<HudIcon>,)>,
+    mut text_query: Query<(&mut Text,), (With<HudText>,)>,
+    mut bar_query: Query<(&HudBar, &mut Style)>,
+) {
+    for (pos, state, right_hand, vitals) in player_query.iter() {
+        let material = right_hand
+            .0
+            .and_then(|entity| item_query.get(entity).ok())
+            .and_then(|(item,)| item_data.icon(item.id()));
+        if let Some(material) = material {
+            icon_query.iter_mut().for_each(|(mut icon,)| {
+                if *icon != material {
+                    *icon = material.clone();
+                }
+            });
+        }
+
+        let value = format!("pos: ({}, {})\nstate: {:?}", pos.x, pos.y, state);
+        text_query.iter_mut().for_each(|(mut text,)| {
+            if text.sections[0].value != value {
+                text.sections[0].value = value.clone();
+            }
+        });
+
+        bar_query.iter_mut().for_each(|(bar, mut style)| {
+            let ratio = match bar {
+                HudBar::Health => vitals.health / vitals.max_health,
+                HudBar::Hunger => vitals.hunger / vitals.max_hunger,
+            };
+            let width = Val::Percent(ratio.max(0.0).min(1.0) * 100.0);
+            if style.size.width != width {
+                style.size.width = width;
+            }
+        });
+    }
+}
@@ -4,39 +4,125 @@ use bevy::{asset::LoadState, prelude::*, reflect::TypeUuid};
 use bevy_asset_ron::RonAssetPlugin;
 use serde::Deserialize;
 
-use super::{textures::SpriteHandles, world::{AddItemToWorldEvent, Position}};
+use crate::agents::player::RightHand;
+
+use super::{
+    clock::GameClock,
+    depth::{Depth, RenderLayer},
+    loading::{AppState, LoadingAssets},
+    textures::SpriteHandles,
+    validation::{self, AssetIssue},
+    world::{Position, WorldItemEvent},
+};
 
 #[derive(Deserialize, TypeUuid, Debug, Default)]
 #[uuid = "e0701840-8dc9-ff6b-80d1-b25acda6107f"]
+#[serde(deny_unknown_fields)]
 pub struct ItemAssets {
-    id: String,
-    name: String,
-    description: String,
-    edible: bool,
-    recovery_amount: u32,
-    portable: bool,
-    installable: bool,
-    collision: bool,
-    texture: String,
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub edible: bool,
+    pub recovery_amount: u32,
+    pub portable: bool,
+    pub installable: bool,
+    pub collision: bool,
+    pub texture: String,
+    // アニメーションさせる場合のフレーム. 空なら texture だけを表示する
+    #[serde(default)]
+    pub frames: Vec<String>,
+    #[serde(default = "default_frame_duration_ms")]
+    pub frame_duration_ms: u64,
+    // 画像のどの点をセルの中心に合わせるか. (0, 0) が左下, (1, 1) が右上
+    #[serde(default = "default_anchor")]
+    pub anchor: (f32, f32),
+    // anchor で合わせた位置からさらにずらす量 (ピクセル)
+    #[serde(default)]
+    pub offset: (f32, f32),
+    // 描画する大きさ (ピクセル). 無ければ画像の大きさ
+    #[serde(default)]
+    pub size: Option<(f32, f32)>,
+    // 持っているときの持ち主からの相対位置. 無ければ設定の items.carry_offset
+    #[serde(default)]
+    pub carry_offset: Option<(f32, f32)>,
+    // 奥行きの補正. 大きいほど手前に描かれる
+    #[serde(default)]
+    pub z_bias: f32,
+}
+
+fn default_frame_duration_ms() -> u64 {
+    200
+}
+
+fn default_anchor() -> (f32, f32) {
+    (0.5, 0.5)
+}
+
+impl ItemAssets {
+    pub fn frame_textures(&self) -> impl Iterator<Item = &str> {
+        let frames: &[String] = if self.frames.is_empty() {
+            std::slice::from_ref(&self.texture)
+        } else {
+            &self.frames
+        };
+        frames.iter().map(|frame| frame.as_str())
+    }
 }
 
 // Resource
 #[derive(Default, Clone)]
 struct ItemsHandles {
     handles: Vec<HandleUntyped>,
-    ron_loaded: bool,
-    material_loaded: bool,
 }
 
 #[derive(Default, Clone)]
 pub struct ItemData {
     item_handle: Handle<ItemAssets>,
-    material_handle: Handle<ColorMaterial>,
+    // 共有 atlas の中の画像. index は atlas の作り直しで変わるのでハンドルで持つ
+    frames: Vec<Handle<Texture>>,
+    frame_duration: f64,
+    anchor: Vec2,
+    offset: Vec2,
+    size: Option<Vec2>,
+    carry_offset: Option<Vec2>,
+    z_bias: f32,
+    // UI は atlas を使えないので HUD 用に最初のフレームの material を持つ
+    icon: Handle<ColorMaterial>,
 }
 
 #[derive(Default, Clone)]
 pub struct ItemDataMap {
     data: HashMap<String, ItemData>,
+    atlas: Handle<TextureAtlas>,
+}
+
+impl ItemDataMap {
+    pub fn get<'a>(&self, item_id: &str, assets: &'a Assets<ItemAssets>) -> Option<&'a ItemAssets> {
+        self.data
+            .get(item_id)
+            .and_then(|item_data| assets.get(&item_data.item_handle))
+    }
+
+    pub fn icon(&self, item_id: &str) -> Option<Handle<ColorMaterial>> {
+        self.data
+            .get(item_id)
+            .map(|item_data| item_data.icon.clone())
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &String> {
+        self.data.keys()
+    }
+
+    pub fn contains(&self, item_id: &str) -> bool {
+        self.data.contains_key(item_id)
+    }
+
+    fn id_of(&self, handle: &Handle<ItemAssets>) -> Option<&str> {
+        self.data
+            .iter()
+            .find(|(_, item_data)| item_data.item_handle == *handle)
+            .map(|(id, _)| id.as_str())
+    }
 }
 
 pub struct Item {
@@ -44,11 +130,30 @@ pub struct Item {
     item_id: String,
 }
 
+impl Item {
+    pub fn id(&self) -> &str {
+        &self.item_id
+    }
+}
+
 pub struct Owner(pub Entity);
 
-pub struct ItemsPlugin;
+// Component
+// 定義の画像の代わりに表示する画像 (オートタイルで隣とつなげた壁など). アニメーションより優先する
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureOverride(pub Handle<Texture>);
 
-pub const ITEM_LAYER: f32 = 10.0;
+// Component
+// 定義から求めた描画位置の補正. ホットリロードに追従するよう毎フレーム更新する
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct ItemOffset {
+    // anchor と offset を合わせたずらし量 (ピクセル)
+    pub offset: Vec2,
+    pub carry_offset: Option<Vec2>,
+    pub z_bias: f32,
+}
+
+pub struct ItemsPlugin;
 
 impl Plugin for ItemsPlugin {
     fn build(&self, app: &mut AppBuilder) {
@@ -59,146 +164,353 @@ impl Plugin for ItemsPlugin {
         .init_resource::<ItemsHandles>()
         .init_resource::<ItemDataMap>()
         .add_startup_system(setup.system())
-        .add_system(load_ron.system())
-        .add_system(load_material.system())
-        .add_system(fixup_textures.system());
+        .add_system_set(
+            SystemSet::on_enter(AppState::Generating)
+                .with_system(load_definitions.system().after("atlas")),
+        )
+        // 読み込み中の Created は load_definitions でまとめて処理する
+        .add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(sync_definitions.system())
+                .with_system(animate_items.system()),
+        );
     }
 }
 
-fn setup(mut prite_handles: ResMut<ItemsHandles>, server: Res<AssetServer>) {
+fn setup(
+    mut prite_handles: ResMut<ItemsHandles>,
+    mut loading_assets: ResMut<LoadingAssets>,
+    server: Res<AssetServer>,
+) {
     prite_handles.handles = server.load_folder("items").unwrap();
+    prite_handles
+        .handles
+        .iter()
+        .for_each(|handle| loading_assets.add(handle.id));
     server.watch_for_changes().unwrap();
 }
 
-fn load_ron(mut handles: ResMut<ItemsHandles>, asset_server: Res<AssetServer>) {
-    if handles.ron_loaded {
-        return;
-    }
-
-    if let LoadState::Loaded =
-        asset_server.get_group_load_state(handles.handles.iter().map(|handle| handle.id))
-    {
-        handles.ron_loaded = true;
-    }
+fn texture_exists(asset_server: &AssetServer, texture: &str) -> bool {
+    matches!(asset_server.get_load_state(texture), LoadState::Loaded)
 }
 
 pub fn spawn_item(
     commands: &mut Commands,
-    event_writer: &mut EventWriter<AddItemToWorldEvent>,
+    event_writer: &mut EventWriter<WorldItemEvent>,
     item_data: &ItemDataMap,
     item_id: &str,
     pos: Position,
 ) {
-    if let Some(item_data) = item_data.data.get(item_id) {
-        let entity = commands
-            .spawn_bundle(SpriteBundle {
-                material: item_data.material_handle.clone(),
-                transform: Transform {
-                    translation: Vec3::new(0.0, 0.0, 0.0),
-                    scale: Vec3::new(1.0, 1.0, 0.0),
-                    rotation: Quat::from_rotation_x(0.0),
-                },
-                ..Default::default()
-            })
-            .insert(Item {
-                item_id: item_id.to_string(),
-            })
-            .insert(pos)
-            .id();
-        event_writer.send(AddItemToWorldEvent(entity, pos));
-    }
-}
-
-fn load_material(
-    mut commands: Commands,
-    mut event_writer: EventWriter<AddItemToWorldEvent>,
+    if let Some(entity) = spawn_item_entity(commands, item_data, item_id) {
+        commands.entity(entity).insert(pos);
+        event_writer.send(WorldItemEvent::Add(entity, pos));
+    }
+}
+
+// Position も Owner も付けずに作る. 置き場所は呼び出し側で決める
+pub fn spawn_item_entity(
+    commands: &mut Commands,
+    item_data: &ItemDataMap,
+    item_id: &str,
+) -> Option<Entity> {
+    let data = item_data.data.get(item_id)?;
+    let entity = commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: item_data.atlas.clone(),
+            transform: Transform {
+                translation: Vec3::new(0.0, 0.0, 0.0),
+                scale: Vec3::new(1.0, 1.0, 0.0),
+                rotation: Quat::from_rotation_x(0.0),
+            },
+            // index が決まるまでは atlas の先頭の画像が見えてしまうので隠しておく
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(Item {
+            item_id: item_id.to_string(),
+        })
+        // anchor の分は画像の大きさが分かってから animate_items で足す
+        .insert(ItemOffset {
+            offset: data.offset,
+            carry_offset: data.carry_offset,
+            z_bias: data.z_bias,
+        })
+        .insert(Depth::new(RenderLayer::Item))
+        .id();
+    Some(entity)
+}
+
+fn load_definitions(
     mut item_data: ResMut<ItemDataMap>,
-    mut handles: ResMut<ItemsHandles>,
-    sprite_handles: ResMut<SpriteHandles>,
+    handles: Res<ItemsHandles>,
+    sprite_handles: Res<SpriteHandles>,
     asset_server: Res<AssetServer>,
     assets: Res<Assets<ItemAssets>>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
     mut material_assets: ResMut<Assets<ColorMaterial>>,
 ) {
-    if handles.material_loaded || !sprite_handles.textures_loaded {
-        return;
+    // 問題のある定義は報告して読み飛ばす
+    let mut issues = Vec::new();
+    item_data.data.clear();
+    item_data.atlas = sprite_handles.atlas.clone();
+    for i in handles.handles.iter() {
+        if let Err(item_issues) = register_item(
+            &i.clone().typed(),
+            &assets,
+            &mut item_data,
+            &asset_server,
+            &texture_atlases,
+            &mut material_assets,
+        ) {
+            issues.extend(item_issues);
+        }
     }
+    validation::report(&issues);
+}
 
-    let mut map = HashMap::new();
-    for i in handles.handles.iter() {
-        let item_assets = assets.get(i).unwrap();
-        let texture: Handle<Texture> = asset_server.get_handle(item_assets.texture.as_str());
-        let material_handle = material_assets.add(texture.into());
-        let item = ItemData {
-            item_handle: i.clone().typed(),
-            material_handle,
-        };
-        map.insert(item_assets.id.clone(), item);
+// 検査して ItemDataMap に登録する. 返り値は登録した id
+fn register_item(
+    handle: &Handle<ItemAssets>,
+    assets: &Assets<ItemAssets>,
+    item_data: &mut ItemDataMap,
+    asset_server: &AssetServer,
+    texture_atlases: &Assets<TextureAtlas>,
+    material_assets: &mut Assets<ColorMaterial>,
+) -> Result<String, Vec<AssetIssue>> {
+    let file = validation::asset_file(asset_server, handle.id);
+    let item_assets = match assets.get(handle) {
+        Some(item_assets) => item_assets,
+        None => {
+            return Err(vec![AssetIssue::new(
+                &file,
+                None,
+                "failed to load (syntax error, unknown or missing field)",
+            )])
+        }
+    };
+    let mut issues = validation::validate_item(&file, item_assets, |texture| {
+        texture_exists(asset_server, texture)
+    });
+    if let Some(other) = item_data.data.get(&item_assets.id) {
+        if other.item_handle != *handle {
+            let first_file = validation::asset_file(asset_server, other.item_handle.id);
+            issues.extend(validation::check_duplicate_id(
+                &file,
+                item_assets,
+                Some(&first_file),
+            ));
+        }
+    }
+    if !issues.is_empty() {
+        return Err(issues);
     }
-    item_data.data = map;
 
-    handles.material_loaded = true;
+    // atlas に詰めるのは textures/ の画像だけ
+    let atlas = texture_atlases.get(&item_data.atlas);
+    let field = if item_assets.frames.is_empty() {
+        "texture"
+    } else {
+        "frames"
+    };
+    let mut frames = Vec::new();
+    for texture in item_assets.frame_textures() {
+        let frame: Handle<Texture> = asset_server.get_handle(texture);
+        if atlas
+            .and_then(|atlas| atlas.get_texture_index(&frame))
+            .is_none()
+        {
+            issues.push(AssetIssue::new(
+                &file,
+                Some(field),
+                format!(
+                    "texture `{}` is not in the texture atlas (textures/)",
+                    texture
+                ),
+            ));
+        }
+        frames.push(frame);
+    }
+    if !issues.is_empty() {
+        return Err(issues);
+    }
 
-    // TODO: delete
-    spawn_item(
-        &mut commands,
-        &mut event_writer,
-        &(*item_data),
-        "berry",
-        Position { x: 2, y: 0 },
-    );
-    spawn_item(
-        &mut commands,
-        &mut event_writer,
-        &(*item_data),
-        "berry",
-        Position { x: 3, y: 0 },
-    );
-    spawn_item(
-        &mut commands,
-        &mut event_writer,
-        &(*item_data),
-        "berry",
-        Position { x: 0, y: 0 },
-    );
-    spawn_item(
-        &mut commands,
-        &mut event_writer,
-        &(*item_data),
-        "wall",
-        Position { x: 1, y: 0 },
+    let item = ItemData {
+        item_handle: handle.clone(),
+        icon: material_assets.add(frames[0].clone().into()),
+        frames,
+        frame_duration: item_assets.frame_duration_ms as f64 / 1000.0,
+        anchor: Vec2::from(item_assets.anchor),
+        offset: Vec2::from(item_assets.offset),
+        size: item_assets.size.map(Vec2::from),
+        carry_offset: item_assets.carry_offset.map(Vec2::from),
+        z_bias: item_assets.z_bias,
+    };
+    item_data.data.insert(item_assets.id.clone(), item);
+    Ok(item_assets.id.clone())
+}
+
+// 定義と atlas から毎フレーム index を引くので, 定義や画像のホットリロードにもそのまま追従する
+fn animate_items(
+    clock: Res<GameClock>,
+    item_data: Res<ItemDataMap>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut query: Query<(
+        &Item,
+        &mut TextureAtlasSprite,
+        &mut Transform,
+        &mut ItemOffset,
+        &mut Visible,
+        Option<&TextureOverride>,
+    )>,
+) {
+    let atlas = match texture_atlases.get(&item_data.atlas) {
+        Some(atlas) => atlas,
+        None => return,
+    };
+    let elapsed = clock.seconds_since_startup();
+    query.iter_mut().for_each(
+        |(item, mut sprite, mut transform, mut item_offset, mut visible, texture_override)| {
+            let data = match item_data.data.get(&item.item_id) {
+                Some(data) => data,
+                None => return,
+            };
+            // 同じアイテムは全部同じ位相で動く
+            let frame = if data.frames.len() > 1 {
+                (elapsed / data.frame_duration) as usize % data.frames.len()
+            } else {
+                0
+            };
+            let texture = texture_override.map_or(&data.frames[frame], |texture| &texture.0);
+            let index = match atlas.get_texture_index(texture) {
+                Some(index) => index,
+                None => return,
+            };
+            if sprite.index != index as u32 {
+                sprite.index = index as u32;
+            }
+            let rect = atlas.textures[index];
+            let image_size = rect.max - rect.min;
+            let size = data.size.unwrap_or(image_size);
+            let scale = size / image_size;
+            if transform.scale.truncate() != scale {
+                transform.scale = scale.extend(transform.scale.z);
+            }
+            let offset = ItemOffset {
+                offset: (Vec2::splat(0.5) - data.anchor) * size + data.offset,
+                carry_offset: data.carry_offset,
+                z_bias: data.z_bias,
+            };
+            if *item_offset != offset {
+                *item_offset = offset;
+            }
+            if !visible.is_visible {
+                visible.is_visible = true;
+            }
+        },
     );
 }
 
-fn fixup_textures(
+#[allow(clippy::too_many_arguments)]
+fn sync_definitions(
+    mut commands: Commands,
     mut ev_asset: EventReader<AssetEvent<ItemAssets>>,
-    assets: ResMut<Assets<ItemAssets>>,
-    mut query: Query<(&mut Handle<ColorMaterial>, &Item)>,
+    mut item_event_writer: EventWriter<WorldItemEvent>,
+    mut handles: ResMut<ItemsHandles>,
+    assets: Res<Assets<ItemAssets>>,
     mut item_data: ResMut<ItemDataMap>,
     asset_server: Res<AssetServer>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
     mut material_assets: ResMut<Assets<ColorMaterial>>,
+    query: Query<(Entity, &Item, Option<&Position>)>,
+    mut hand_query: Query<(&mut RightHand,)>,
 ) {
+    let mut removed_handles = Vec::new();
+    let mut removed_ids = Vec::new();
+    let mut issues = Vec::new();
     for ev in ev_asset.iter() {
         match ev {
-            AssetEvent::Created { .. } => {}
-            AssetEvent::Modified { handle } => {
-                let item_assets = assets.get(handle).unwrap();
-                let texture: Handle<Texture> =
-                    asset_server.get_handle(item_assets.texture.as_str());
-                let material_handle = material_assets.add(texture.into());
-                let item = ItemData {
-                    item_handle: handle.clone(),
-                    material_handle: material_handle.clone(),
-                };
-                item_data.data.insert(item_assets.id.clone(), item);
-                query.iter_mut().for_each(|(mut material, item)| {
-                    if item.item_id == item_assets.id {
-                        *material = material_handle.clone();
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                // watcher が読み込んだ新しいファイルは, ハンドルを持っておかないと解放される
+                if !handles.handles.iter().any(|other| other.id == handle.id) {
+                    handles
+                        .handles
+                        .push(asset_server.get_handle_untyped(handle.id));
+                }
+                // id が変わった場合は古い id の定義を消す
+                let old_id = item_data.id_of(handle).map(|id| id.to_string());
+                match register_item(
+                    handle,
+                    &assets,
+                    &mut item_data,
+                    &asset_server,
+                    &texture_atlases,
+                    &mut material_assets,
+                ) {
+                    Ok(id) => {
+                        if let Some(old_id) = old_id.filter(|old_id| *old_id != id) {
+                            item_data.data.remove(&old_id);
+                            removed_ids.push(old_id);
+                        }
                     }
-                })
+                    // 直前の正しい定義を使い続ける
+                    Err(item_issues) => issues.extend(item_issues),
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                handles.handles.retain(|other| other.id != handle.id);
+                removed_handles.push(handle.clone());
             }
-            AssetEvent::Removed { .. } => {}
         }
     }
+    for handle in removed_handles.iter() {
+        if let Some(id) = item_data.id_of(handle).map(|id| id.to_string()) {
+            item_data.data.remove(&id);
+            removed_ids.push(id);
+        }
+    }
+
+    for id in removed_ids {
+        // 消えた id を同じ id を持つ別のファイルが定義していればそれで置き換える
+        let replaced = handles
+            .handles
+            .iter()
+            .map(|handle| handle.clone().typed::<ItemAssets>())
+            .filter(|handle| !removed_handles.contains(handle))
+            .filter(|handle| assets.get(handle).map(|item| item.id == id) == Some(true))
+            .any(|handle| {
+                register_item(
+                    &handle,
+                    &assets,
+                    &mut item_data,
+                    &asset_server,
+                    &texture_atlases,
+                    &mut material_assets,
+                )
+                .is_ok()
+            });
+        if replaced {
+            continue;
+        }
+        // 定義が無くなったアイテムは消す
+        query
+            .iter()
+            .filter(|(_, item, _)| item.item_id == id)
+            .for_each(|(entity, _, pos)| {
+                if let Some(pos) = pos {
+                    item_event_writer.send(WorldItemEvent::Remove(entity, *pos));
+                }
+                hand_query.iter_mut().for_each(|(mut right_hand,)| {
+                    if right_hand.0 == Some(entity) {
+                        right_hand.0 = None;
+                    }
+                });
+                commands.entity(entity).despawn();
+            });
+    }
+    validation::report(&issues);
 }
 
 // fn print_config(assets: Res<Assets<ItemAssets>>, handles: Res<ItemsHandles>) {
@@ -0,0 +1,120 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{chunk::TileLayer, world::Position};
+
+// エディタが書き出すレベル. タイルは画像のパスで持つ (atlas の index は作り直しで変わる)
+// overlay (霧) は遊んでいる間の状態なので書き出さない
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LevelFile {
+    // tiles[0] のセル. そこから x が先に増える
+    pub origin: (i32, i32),
+    pub size: (u32, u32),
+    // tiles が指す画像のパス
+    pub palette: Vec<String>,
+    pub tiles: Vec<Option<usize>>,
+    // 装飾の層. tiles と同じ並び
+    #[serde(default)]
+    pub decorations: Vec<Option<usize>>,
+    pub items: Vec<((i32, i32), String)>,
+}
+
+// 読み込んだレベルをマップに当てる. エディタが 1 回分の undo として反映する
+pub struct LoadLevelEvent(pub LevelFile);
+
+impl LevelFile {
+    pub fn new(min: Position, max: Position) -> Self {
+        let size = ((max.x - min.x + 1) as u32, (max.y - min.y + 1) as u32);
+        LevelFile {
+            origin: (min.x, min.y),
+            size,
+            palette: Vec::new(),
+            tiles: vec![None; (size.0 * size.1) as usize],
+            decorations: vec![None; (size.0 * size.1) as usize],
+            items: Vec::new(),
+        }
+    }
+
+    fn index(&self, pos: Position) -> Option<usize> {
+        let (x, y) = (pos.x - self.origin.0, pos.y - self.origin.1);
+        if x < 0 || y < 0 || x >= self.size.0 as i32 || y >= self.size.1 as i32 {
+            return None;
+        }
+        Some((y * self.size.0 as i32 + x) as usize)
+    }
+
+    fn layer(&self, layer: TileLayer) -> Option<&Vec<Option<usize>>> {
+        match layer {
+            TileLayer::Ground => Some(&self.tiles),
+            TileLayer::Decoration => Some(&self.decorations),
+            TileLayer::Overlay => None,
+        }
+    }
+
+    pub fn tile(&self, pos: Position, layer: TileLayer) -> Option<&str> {
+        let index = self.index(pos)?;
+        let tile = (*self.layer(layer)?.get(index)?)?;
+        self.palette.get(tile).map(|texture| texture.as_str())
+    }
+
+    pub fn set_tile(&mut self, pos: Position, layer: TileLayer, texture: Option<&str>) {
+        let index = match self.index(pos) {
+            Some(index) => index,
+            None => return,
+        };
+        // 書き出さない層の画像を palette に入れない
+        if self.layer(layer).is_none() {
+            return;
+        }
+        let tile = texture.map(|texture| self.palette_index(texture));
+        let cells = match layer {
+            TileLayer::Ground => &mut self.tiles,
+            TileLayer::Decoration => &mut self.decorations,
+            TileLayer::Overlay => return,
+        };
+        // decorations の無い古いファイルを読んだときに備えて伸ばす
+        if cells.len() <= index {
+            cells.resize(index + 1, None);
+        }
+        cells[index] = tile;
+    }
+
+    fn palette_index(&mut self, texture: &str) -> usize {
+        match self.palette.iter().position(|path| path == texture) {
+            Some(tile) => tile,
+            None => {
+                self.palette.push(texture.to_string());
+                self.palette.len() - 1
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = fs::read(path)?;
+        let level: LevelFile = ron::de::from_bytes(&bytes)?;
+        // 手で書き換えたファイルの壊れた index は読み込みで弾く
+        if let Some(tile) = level
+            .tiles
+            .iter()
+            .chain(level.decorations.iter())
+            .flatten()
+            .find(|tile| **tile >= level.palette.len())
+        {
+            anyhow::bail!(
+                "tile {} is out of the palette ({} textures)",
+                tile,
+                level.palette.len()
+            );
+        }
+        Ok(level)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, ron::ser::to_string(self)?)?;
+        Ok(())
+    }
+}
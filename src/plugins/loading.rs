@@ -0,0 +1,166 @@
+use bevy::{
+    asset::{HandleId, LoadState},
+    prelude::*,
+};
+
+use super::{config::GameConfig, ui::UiAssets};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AppState {
+    // アセットの読み込み待ち
+    Loading,
+    // atlas, アイテム定義, マップを作る
+    Generating,
+    Playing,
+}
+
+// Resource
+// 各プラグインが setup で読み込み待ちにしたいハンドルを登録する
+#[derive(Default)]
+pub struct LoadingAssets {
+    handles: Vec<HandleId>,
+}
+
+impl LoadingAssets {
+    pub fn add<H: Into<HandleId>>(&mut self, handle: H) {
+        self.handles.push(handle.into());
+    }
+
+    // (読み込みが終わった数, 全体の数). 失敗したものも終わったとみなす
+    pub fn progress(&self, asset_server: &AssetServer) -> (usize, usize) {
+        let settled = self
+            .handles
+            .iter()
+            .filter(|id| {
+                matches!(
+                    asset_server.get_load_state(**id),
+                    LoadState::Loaded | LoadState::Failed
+                )
+            })
+            .count();
+        (settled, self.handles.len())
+    }
+}
+
+struct LoadingScreen;
+struct LoadingText;
+struct LoadingBar;
+
+const BAR_WIDTH: f32 = 320.0;
+const BAR_HEIGHT: f32 = 12.0;
+
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_state(AppState::Loading)
+            .init_resource::<LoadingAssets>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::Loading).with_system(spawn_loading_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Loading).with_system(check_loading.system()),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Generating).with_system(show_generating.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Generating)
+                    .with_system(despawn_loading_screen.system()),
+            );
+    }
+}
+
+fn spawn_loading_screen(
+    mut commands: Commands,
+    ui_assets: Res<UiAssets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::BLACK.into()),
+            ..Default::default()
+        })
+        .insert(LoadingScreen)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "Loading...",
+                        ui_assets.text_style(20.0),
+                        Default::default(),
+                    ),
+                    style: Style {
+                        margin: Rect::all(Val::Px(8.0)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(LoadingText);
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(BAR_WIDTH), Val::Px(BAR_HEIGHT)),
+                        ..Default::default()
+                    },
+                    material: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                                ..Default::default()
+                            },
+                            material: materials.add(Color::rgb(0.4, 0.8, 0.4).into()),
+                            ..Default::default()
+                        })
+                        .insert(LoadingBar);
+                });
+        });
+}
+
+fn check_loading(
+    mut state: ResMut<State<AppState>>,
+    loading_assets: Res<LoadingAssets>,
+    config: Res<GameConfig>,
+    asset_server: Res<AssetServer>,
+    mut bar_query: Query<(&mut Style,), (With<LoadingBar>,)>,
+) {
+    let (settled, total) = loading_assets.progress(&asset_server);
+    let ratio = if total == 0 {
+        1.0
+    } else {
+        settled as f32 / total as f32
+    };
+    bar_query.iter_mut().for_each(|(mut style,)| {
+        if style.size.width != Val::Percent(ratio * 100.0) {
+            style.size.width = Val::Percent(ratio * 100.0);
+        }
+    });
+
+    // 設定は読み込み後の Created イベントで GameConfig に反映されるのでそれも待つ
+    if settled == total && config.loaded {
+        state.set(AppState::Generating).unwrap();
+    }
+}
+
+fn show_generating(mut query: Query<(&mut Text,), (With<LoadingText>,)>) {
+    query.iter_mut().for_each(|(mut text,)| {
+        text.sections[0].value = "Generating world...".to_string();
+    });
+}
+
+fn despawn_loading_screen(mut commands: Commands, query: Query<Entity, With<LoadingScreen>>) {
+    query.iter().for_each(|entity| {
+        commands.entity(entity).despawn_recursive();
+    });
+}
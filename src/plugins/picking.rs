@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+
+use super::{
+    camera::MainCamera,
+    depth::{self, RenderLayer},
+    loading::AppState,
+    world::{GameWorld, Position, TILE_SIZE},
+};
+
+// Resource
+#[derive(Default, Clone, Debug)]
+pub struct HoveredCell {
+    pub pos: Option<Position>,
+    pub item: Option<Entity>,
+}
+
+pub struct CellClickEvent {
+    pub pos: Position,
+    pub button: MouseButton,
+    pub item: Option<Entity>,
+}
+
+pub struct HoverHighlight;
+
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<HoveredCell>()
+            .add_event::<CellClickEvent>()
+            .add_startup_system(setup.system())
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(hover.system().label("picking").after("camera"))
+                    .with_system(click.system().after("picking"))
+                    .with_system(highlight.system().after("picking")),
+            );
+    }
+}
+
+fn setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(TILE_SIZE, TILE_SIZE)),
+            material: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.25).into()),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(HoverHighlight);
+}
+
+// ウィンドウ座標 (左下原点) をワールド座標へ
+pub fn screen_to_world(window: &Window, camera_transform: &Transform, cursor: Vec2) -> Vec2 {
+    let window_size = Vec2::new(window.width(), window.height());
+    let offset = (cursor - window_size / 2.0) * camera_transform.scale.truncate();
+    camera_transform.translation.truncate() + offset
+}
+
+// Transform.translation.z (depth の sort key) は正射影なので xy には影響しない
+pub fn screen_to_grid(window: &Window, camera_transform: &Transform, cursor: Vec2) -> Position {
+    Position::from_world(screen_to_world(window, camera_transform, cursor))
+}
+
+fn hover(
+    windows: Res<Windows>,
+    mut hovered: ResMut<HoveredCell>,
+    camera_query: Query<(&Transform,), (With<MainCamera>,)>,
+    world_query: Query<(&GameWorld,)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let cursor = match window.cursor_position() {
+        Some(cursor) => cursor,
+        None => {
+            hovered.pos = None;
+            hovered.item = None;
+            return;
+        }
+    };
+    for (camera_transform,) in camera_query.iter() {
+        let pos = screen_to_grid(window, camera_transform, cursor);
+        hovered.pos = Some(pos);
+        hovered.item = world_query
+            .iter()
+            .find_map(|(world,)| world.item_map.get(&pos).copied());
+    }
+}
+
+fn click(
+    mouse_input: Res<Input<MouseButton>>,
+    hovered: Res<HoveredCell>,
+    mut event_writer: EventWriter<CellClickEvent>,
+) {
+    if let Some(pos) = hovered.pos {
+        for button in mouse_input.get_just_pressed() {
+            event_writer.send(CellClickEvent {
+                pos,
+                button: *button,
+                item: hovered.item,
+            });
+        }
+    }
+}
+
+fn highlight(
+    hovered: Res<HoveredCell>,
+    mut query: Query<(&mut Transform, &mut Visible), (With<HoverHighlight>,)>,
+) {
+    if !hovered.is_changed() {
+        return;
+    }
+    query
+        .iter_mut()
+        .for_each(|(mut transform, mut visible)| match hovered.pos {
+            Some(pos) => {
+                let world = pos.to_world();
+                transform.translation =
+                    world.extend(depth::sort_z(RenderLayer::GroundOverlay, 0.0, 0.0));
+                visible.is_visible = true;
+            }
+            None => visible.is_visible = false,
+        });
+}
@@ -0,0 +1,419 @@
+use std::{
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use bevy::{app::AppExit, ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::agents::player::{InputCommands, PlayerState, RightHand};
+
+use super::{
+    clock::SIMULATION,
+    config::{GameConfig, MovementConfig, TerrainConfig},
+    items::Item,
+    world::{GameWorld, OldPosition, Position, WorldSeed},
+};
+
+// 落ちても記録が残るよう, この tick 数ごとにファイルへ書き出す
+const SAVE_INTERVAL_TICKS: usize = 300;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// FNV-1a. DefaultHasher は Rust のバージョンで結果が変わりうるので, 保存する hash にはこちらを使う.
+// 整数は環境に依らないよう little endian の 8 バイトに揃えて書く
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+// 記録した入力の結果を左右する設定. 再生するときの data.config と違えば流さない
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReplayConfig {
+    pub tick_rate: u32,
+    pub movement: MovementConfig,
+    pub chunks: (u32, u32),
+    pub chunk_size: (u32, u32),
+    pub terrain: TerrainConfig,
+}
+
+impl ReplayConfig {
+    pub fn new(config: &GameConfig) -> Self {
+        ReplayConfig {
+            tick_rate: config.simulation.tick_rate,
+            movement: config.movement.clone(),
+            chunks: config.world.chunks,
+            chunk_size: config.world.chunk_size,
+            terrain: config.terrain.clone(),
+        }
+    }
+
+    // 値の違う設定の名前
+    pub fn differences(&self, other: &ReplayConfig) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.tick_rate != other.tick_rate {
+            fields.push("simulation.tick_rate");
+        }
+        if self.movement != other.movement {
+            fields.push("movement");
+        }
+        if self.chunks != other.chunks {
+            fields.push("world.chunks");
+        }
+        if self.chunk_size != other.chunk_size {
+            fields.push("world.chunk_size");
+        }
+        if self.terrain != other.terrain {
+            fields.push("terrain");
+        }
+        fields
+    }
+}
+
+// リプレイファイルの中身
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReplayFile {
+    pub seed: u64,
+    // 古いリプレイには無い
+    #[serde(default)]
+    pub config: Option<ReplayConfig>,
+    // i 番目が i tick 目の入力
+    pub actions: Vec<InputCommands>,
+    // actions を全部流し終えた時点の world_hash
+    pub final_hash: u64,
+}
+
+impl ReplayFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = fs::read(path)?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, ron::ser::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+// Resource
+// 記録中と再生中はコンソールとエディタから世界を変えられない. 入力しか記録しないので再生とずれる
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayMode {
+    Off,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+impl ReplayMode {
+    pub fn is_active(&self) -> bool {
+        *self != ReplayMode::Off
+    }
+
+    // `--record <file>` か `--replay <file>`. 後に書いた方が優先
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut mode = ReplayMode::Off;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => {
+                    if let Some(path) = args.next() {
+                        mode = ReplayMode::Record(path.into());
+                    }
+                }
+                "--replay" => {
+                    if let Some(path) = args.next() {
+                        mode = ReplayMode::Replay(path.into());
+                    }
+                }
+                _ => {}
+            }
+        }
+        mode
+    }
+}
+
+// Resource
+struct Recorder {
+    path: PathBuf,
+    // 最初の tick の設定
+    config: Option<ReplayConfig>,
+    actions: Vec<InputCommands>,
+}
+
+impl Recorder {
+    // snapshot は actions を全部流した後の状態. hash は保存するときだけ取る
+    fn save(&self, snapshot: &WorldSnapshot) {
+        let file = ReplayFile {
+            seed: snapshot.seed.0,
+            config: self.config.clone(),
+            actions: self.actions.clone(),
+            final_hash: snapshot.hash(),
+        };
+        match file.save(&self.path) {
+            Ok(()) => info!(
+                "saved replay ({} ticks) to {}",
+                self.actions.len(),
+                self.path.display()
+            ),
+            Err(err) => error!("failed to save replay {}: {}", self.path.display(), err),
+        }
+    }
+}
+
+// Resource
+pub struct ReplayPlayer {
+    file: ReplayFile,
+    cursor: usize,
+    // 流し終えたら hash が一致したか
+    result: Option<bool>,
+}
+
+impl ReplayPlayer {
+    pub fn result(&self) -> Option<bool> {
+        self.result
+    }
+}
+
+// world_hash に使う状態
+#[derive(SystemParam)]
+pub struct WorldSnapshot<'a> {
+    seed: Res<'a, WorldSeed>,
+    players: Query<
+        'a,
+        (
+            &'static Position,
+            &'static OldPosition,
+            &'static PlayerState,
+            &'static RightHand,
+        ),
+    >,
+    items: Query<'a, (Entity, &'static Item, Option<&'static Position>)>,
+    worlds: Query<'a, &'static GameWorld>,
+}
+
+impl<'a> WorldSnapshot<'a> {
+    // Entity は実行ごとに変わりうるのでアイテムの id に置き換え, 並べ替えてから hash を取る
+    pub fn hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.seed.0.hash(&mut hasher);
+
+        let item_id = |entity: Entity| self.items.get(entity).ok().map(|(_, item, _)| item.id());
+
+        let mut players = self
+            .players
+            .iter()
+            .map(|(pos, old_pos, state, right_hand)| {
+                let state = match state {
+                    PlayerState::Idle => (0, (0, 0), None),
+                    PlayerState::Walk(walk) => (1, *walk, None),
+                    PlayerState::Pick(entity) => (2, (0, 0), item_id(*entity)),
+                    PlayerState::Drop => (3, (0, 0), None),
+                    PlayerState::PickAndDrop(entity) => (4, (0, 0), item_id(*entity)),
+                };
+                (
+                    (pos.x, pos.y),
+                    (old_pos.x, old_pos.y),
+                    state,
+                    right_hand.0.and_then(item_id),
+                )
+            })
+            .collect::<Vec<_>>();
+        players.sort();
+        players.hash(&mut hasher);
+
+        let mut items = self
+            .items
+            .iter()
+            .map(|(_, item, pos)| (item.id(), pos.map(|pos| (pos.x, pos.y))))
+            .collect::<Vec<_>>();
+        items.sort();
+        items.hash(&mut hasher);
+
+        let mut item_map = self
+            .worlds
+            .iter()
+            .flat_map(|world| world.item_map.iter())
+            .map(|(pos, entity)| ((pos.x, pos.y), item_id(*entity)))
+            .collect::<Vec<_>>();
+        item_map.sort();
+        item_map.hash(&mut hasher);
+
+        hasher.finish()
+    }
+}
+
+pub struct ReplayPlugin {
+    pub mode: ReplayMode,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(self.mode.clone());
+        match &self.mode {
+            ReplayMode::Off => {}
+            ReplayMode::Record(path) => {
+                app.insert_resource(Recorder {
+                    path: path.clone(),
+                    config: None,
+                    actions: Vec::new(),
+                })
+                .add_system_to_stage(
+                    SIMULATION,
+                    record
+                        .system()
+                        .after("read_input")
+                        .before("history")
+                        .before("input"),
+                )
+                .add_system_to_stage(CoreStage::Last, save_on_exit.system());
+            }
+            ReplayMode::Replay(path) => match ReplayFile::load(path) {
+                Ok(file) => {
+                    // マップ生成より前に種を差し替える
+                    app.insert_resource(WorldSeed(file.seed))
+                        .insert_resource(ReplayPlayer {
+                            file,
+                            cursor: 0,
+                            result: None,
+                        })
+                        .add_system_to_stage(
+                            SIMULATION,
+                            replay
+                                .system()
+                                .after("read_input")
+                                .before("history")
+                                .before("input"),
+                        );
+                }
+                Err(err) => error!("failed to load replay {}: {}", path.display(), err),
+            },
+        }
+    }
+}
+
+// この tick の入力を流す前に保存するので, 保存した actions と hash が常に揃う
+fn record(
+    mut recorder: ResMut<Recorder>,
+    input_commands: Res<InputCommands>,
+    config: Res<GameConfig>,
+    snapshot: WorldSnapshot,
+) {
+    let current = ReplayConfig::new(&config);
+    match &recorder.config {
+        None => recorder.config = Some(current),
+        Some(recorded) => {
+            let fields = recorded.differences(&current);
+            if !fields.is_empty() {
+                // 記録した設定のまま再生するので, 途中で変えた分はずれる
+                warn!(
+                    "{} changed while recording, the replay will diverge",
+                    fields.join(", ")
+                );
+                recorder.config = Some(current);
+            }
+        }
+    }
+    let len = recorder.actions.len();
+    if len > 0 && len % SAVE_INTERVAL_TICKS == 0 {
+        recorder.save(&snapshot);
+    }
+    recorder.actions.push(*input_commands);
+}
+
+// Last では最後の tick の入力まで流し終えている
+fn save_on_exit(
+    mut events: EventReader<AppExit>,
+    recorder: Res<Recorder>,
+    snapshot: WorldSnapshot,
+) {
+    if events.iter().count() > 0 {
+        recorder.save(&snapshot);
+    }
+}
+
+// キーボードの入力を記録した入力で上書きする. 流し終えたら hash を比べて止まる
+fn replay(
+    mut player: ResMut<ReplayPlayer>,
+    mut input_commands: ResMut<InputCommands>,
+    config: Res<GameConfig>,
+    snapshot: WorldSnapshot,
+) {
+    if player.result.is_some() {
+        *input_commands = InputCommands::default();
+        return;
+    }
+    if player.cursor == 0 {
+        match &player.file.config {
+            Some(recorded) => {
+                let fields = recorded.differences(&ReplayConfig::new(&config));
+                if !fields.is_empty() {
+                    error!(
+                        "replay was recorded with different {}, not replaying",
+                        fields.join(", ")
+                    );
+                    player.result = Some(false);
+                    *input_commands = InputCommands::default();
+                    return;
+                }
+            }
+            None => warn!("replay has no recorded config, it may diverge"),
+        }
+    }
+    if player.cursor < player.file.actions.len() {
+        *input_commands = player.file.actions[player.cursor];
+        player.cursor += 1;
+        return;
+    }
+
+    let hash = snapshot.hash();
+    if hash == player.file.final_hash {
+        info!(
+            "replay finished after {} ticks: world hash matches ({:016x})",
+            player.cursor, hash
+        );
+    } else {
+        error!(
+            "replay diverged after {} ticks: expected world hash {:016x}, got {:016x}",
+            player.cursor, player.file.final_hash, hash
+        );
+    }
+    player.result = Some(hash == player.file.final_hash);
+    *input_commands = InputCommands::default();
+}
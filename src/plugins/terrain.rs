@@ -0,0 +1,106 @@
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    config::{BiomeConfig, TerrainConfig},
+    world::Position,
+};
+
+// 高さと湿り気が同じ模様にならないよう種をずらす
+const MOISTURE_SEED_SALT: u32 = 0x6d6f_6973;
+
+// 生成したチャンクの中身. 地面は画像のパス, アイテムは id
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkContent {
+    pub tiles: Vec<(Position, String)>,
+    pub items: Vec<(Position, String)>,
+}
+
+// チャンクの左下のセルから乱数の種を作る. 他のチャンクを生成したかどうかに依らない
+pub fn chunk_seed(seed: u64, min: Position) -> u64 {
+    // splitmix64. 隣のチャンクが似た乱数列にならないよう混ぜる
+    let cell = (min.x as u32 as u64) << 32 | min.y as u32 as u64;
+    let mut z = (seed ^ cell).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub struct TerrainGenerator<'a> {
+    config: &'a TerrainConfig,
+    seed: u64,
+    height: Fbm,
+    moisture: Fbm,
+}
+
+impl<'a> TerrainGenerator<'a> {
+    pub fn new(seed: u64, config: &'a TerrainConfig) -> Self {
+        // Fbm はオクターブごとに seed + i を使うので溢れないよう上のビットを落とす
+        let noise = |seed: u32, frequency: f64| {
+            Fbm::new()
+                .set_seed(seed & 0x7fff_ffff)
+                .set_octaves(config.octaves)
+                .set_frequency(frequency)
+        };
+        TerrainGenerator {
+            config,
+            seed,
+            height: noise(seed as u32, config.height_frequency),
+            moisture: noise(
+                (seed >> 32) as u32 ^ MOISTURE_SEED_SALT,
+                config.moisture_frequency,
+            ),
+        }
+    }
+
+    // 0..=1 に直した (高さ, 湿り気)
+    pub fn sample(&self, pos: Position) -> (f64, f64) {
+        let point = [pos.x as f64, pos.y as f64];
+        let unit = |value: f64| ((value + 1.0) / 2.0).max(0.0).min(1.0);
+        (unit(self.height.get(point)), unit(self.moisture.get(point)))
+    }
+
+    pub fn biome(&self, pos: Position) -> Option<&'a BiomeConfig> {
+        let (height, moisture) = self.sample(pos);
+        let contains = |(min, max): (f64, f64), value: f64| min <= value && value <= max;
+        self.config
+            .biomes
+            .iter()
+            .find(|biome| contains(biome.height, height) && contains(biome.moisture, moisture))
+            .or_else(|| self.config.biomes.last())
+    }
+
+    // min..=max のセルを生成する. 同じ seed とチャンクなら常に同じ中身になる
+    pub fn generate_chunk(&self, min: Position, max: Position) -> ChunkContent {
+        let mut rng = StdRng::seed_from_u64(chunk_seed(self.seed, min));
+        let clearance = self.config.spawn_clearance;
+        let mut content = ChunkContent::default();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pos = Position { x, y };
+                let biome = match self.biome(pos) {
+                    Some(biome) => biome,
+                    None => continue,
+                };
+                if !biome.tiles.is_empty() {
+                    let tile = &biome.tiles[rng.gen_range(0..biome.tiles.len())];
+                    content.tiles.push((pos, tile.clone()));
+                }
+                // 乱数を引く回数をセルによって変えないよう, 置かないセルでも引く
+                let roll = rng.gen::<f32>();
+                if x.abs() <= clearance && y.abs() <= clearance {
+                    continue;
+                }
+                let mut threshold = 0.0;
+                for rule in biome.items.iter() {
+                    threshold += rule.chance;
+                    if roll < threshold {
+                        content.items.push((pos, rule.item.clone()));
+                        break;
+                    }
+                }
+            }
+        }
+        content
+    }
+}
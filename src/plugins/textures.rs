@@ -1,11 +1,13 @@
-use bevy::{asset::LoadState, prelude::*};
+use bevy::{prelude::*, sprite::TextureAtlasBuilder};
+
+use super::loading::{AppState, LoadingAssets};
 
 #[derive(Default, Clone)]
 pub struct SpriteHandles {
     pub textures_handles: Vec<HandleUntyped>,
-    pub textures_loaded: bool,
     pub sprites_handles: Vec<HandleUntyped>,
-    pub sprites_loaded: bool,
+    // textures/ の画像をまとめた atlas. タイルとアイテムで共有する
+    pub atlas: Handle<TextureAtlas>,
 }
 
 pub struct TexturePlugin;
@@ -14,43 +16,44 @@ impl Plugin for TexturePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<SpriteHandles>()
             .add_startup_system(setup.system())
-            .add_system(textures_load.system())
-            .add_system(sprites_load.system());
+            .add_system_set(
+                SystemSet::on_enter(AppState::Generating)
+                    .with_system(setup_atlas.system().label("atlas")),
+            );
     }
 }
 
-fn setup(mut sprite_handles: ResMut<SpriteHandles>, asset_server: Res<AssetServer>) {
+fn setup(
+    mut sprite_handles: ResMut<SpriteHandles>,
+    mut loading_assets: ResMut<LoadingAssets>,
+    asset_server: Res<AssetServer>,
+) {
     sprite_handles.textures_handles = asset_server.load_folder("textures").unwrap();
     sprite_handles.sprites_handles = asset_server.load_folder("sprites").unwrap();
+    sprite_handles
+        .textures_handles
+        .iter()
+        .chain(sprite_handles.sprites_handles.iter())
+        .for_each(|handle| loading_assets.add(handle.id));
     asset_server.watch_for_changes().unwrap();
 }
 
-fn textures_load(
-    mut sprite_handles: ResMut<SpriteHandles>,
-    asset_server: Res<AssetServer>,
-) {
-    if sprite_handles.textures_loaded {
-        return;
-    }
-
-    if let LoadState::Loaded =
-        asset_server.get_group_load_state(sprite_handles.textures_handles.iter().map(|handle| handle.id))
-    {
-        sprite_handles.textures_loaded = true;
+pub fn build_atlas(sprite_handles: &SpriteHandles, textures: &mut Assets<Texture>) -> TextureAtlas {
+    let mut texture_atlas_builder = TextureAtlasBuilder::default();
+    for handle in sprite_handles.textures_handles.iter() {
+        // 読み込みに失敗した画像は atlas に含めない
+        if let Some(texture) = textures.get(handle) {
+            texture_atlas_builder.add_texture(handle.clone_weak().typed::<Texture>(), texture);
+        }
     }
+    texture_atlas_builder.finish(textures).unwrap()
 }
 
-fn sprites_load(
+fn setup_atlas(
     mut sprite_handles: ResMut<SpriteHandles>,
-    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut textures: ResMut<Assets<Texture>>,
 ) {
-    if sprite_handles.sprites_loaded {
-        return;
-    }
-
-    if let LoadState::Loaded =
-        asset_server.get_group_load_state(sprite_handles.sprites_handles.iter().map(|handle| handle.id))
-    {
-        sprite_handles.sprites_loaded = true;
-    }
+    let texture_atlas = build_atlas(&sprite_handles, &mut textures);
+    sprite_handles.atlas = texture_atlases.add(texture_atlas);
 }
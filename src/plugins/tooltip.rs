@@ -0,0 +1,131 @@
+use bevy::prelude::*;
+
+use crate::agents::player::PlayerState;
+
+use super::{
+    items::{Item, ItemAssets, ItemDataMap},
+    loading::AppState,
+    picking::HoveredCell,
+    ui::UiAssets,
+    world::{GameWorld, Position},
+};
+
+const TOOLTIP_MARGIN: f32 = 16.0;
+
+struct TooltipRoot;
+struct TooltipText;
+
+pub struct TooltipPlugin;
+
+impl Plugin for TooltipPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(setup.system().after("ui_setup"))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(update_tooltip.system().after("picking")),
+            );
+    }
+}
+
+fn setup(mut commands: Commands, ui_assets: Res<UiAssets>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                padding: Rect::all(Val::Px(6.0)),
+                ..Default::default()
+            },
+            material: ui_assets.panel.clone(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(TooltipRoot)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section("", ui_assets.text_style(14.0), Default::default()),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: true,
+                    },
+                    ..Default::default()
+                })
+                .insert(TooltipText);
+        });
+}
+
+fn tooltip_text(item_assets: &ItemAssets) -> String {
+    let mut flags = Vec::new();
+    if item_assets.edible {
+        flags.push("edible");
+    }
+    if item_assets.portable {
+        flags.push("portable");
+    }
+    let mut text = format!("{}\n{}", item_assets.name, item_assets.description);
+    if !flags.is_empty() {
+        text.push_str(&format!("\n[{}]", flags.join(", ")));
+    }
+    text
+}
+
+// ホバー中のアイテムを優先し, 無ければプレイヤーの足元のアイテムを表示する
+#[allow(clippy::too_many_arguments)]
+fn update_tooltip(
+    hovered: Res<HoveredCell>,
+    item_data: Res<ItemDataMap>,
+    item_assets: Res<Assets<ItemAssets>>,
+    windows: Res<Windows>,
+    player_query: Query<(&Position,), (With<PlayerState>,)>,
+    world_query: Query<(&GameWorld,)>,
+    item_query: Query<(&Item,)>,
+    mut root_query: Query<(&mut Style, &mut Visible), (With<TooltipRoot>, Without<TooltipText>)>,
+    mut text_query: Query<(&mut Text, &mut Visible), (With<TooltipText>, Without<TooltipRoot>)>,
+) {
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position());
+    let (item_entity, anchor) = match (hovered.item, cursor) {
+        (Some(item_entity), Some(cursor)) => {
+            (Some(item_entity), cursor + Vec2::splat(TOOLTIP_MARGIN))
+        }
+        _ => {
+            let standing = player_query.iter().find_map(|(pos,)| {
+                world_query
+                    .iter()
+                    .find_map(|(world,)| world.item_map.get(pos).copied())
+            });
+            (standing, Vec2::new(TOOLTIP_MARGIN, TOOLTIP_MARGIN))
+        }
+    };
+
+    // アセットは毎フレーム引き直すので .item のホットリロードにも追従する
+    let text = item_entity
+        .and_then(|entity| item_query.get(entity).ok())
+        .and_then(|(item,)| item_data.get(item.id(), &item_assets))
+        .map(tooltip_text);
+
+    root_query.iter_mut().for_each(|(mut style, mut visible)| {
+        visible.is_visible = text.is_some();
+        if text.is_some()
+            && (style.position.left != Val::Px(anchor.x)
+                || style.position.bottom != Val::Px(anchor.y))
+        {
+            style.position.left = Val::Px(anchor.x);
+            style.position.bottom = Val::Px(anchor.y);
+        }
+    });
+    text_query
+        .iter_mut()
+        .for_each(|(mut ui_text, mut visible)| {
+            visible.is_visible = text.is_some();
+            if let Some(text) = &text {
+                if ui_text.sections[0].value != *text {
+                    ui_text.sections[0].value = text.clone();
+                }
+            }
+        });
+}
@@ -0,0 +1,44 @@
+use bevy::prelude::*;
+
+use super::loading::LoadingAssets;
+
+pub const UI_FONT: &str = "fonts/DejaVuSansMono.ttf";
+
+// Resource
+#[derive(Default, Clone)]
+pub struct UiAssets {
+    pub font: Handle<Font>,
+    pub panel: Handle<ColorMaterial>,
+}
+
+impl UiAssets {
+    pub fn text_style(&self, font_size: f32) -> TextStyle {
+        TextStyle {
+            font: self.font.clone(),
+            font_size,
+            color: Color::WHITE,
+        }
+    }
+}
+
+pub struct UiPlugin;
+
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<UiAssets>()
+            .add_startup_system(setup.system().label("ui_setup"));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut ui_assets: ResMut<UiAssets>,
+    mut loading_assets: ResMut<LoadingAssets>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    ui_assets.font = asset_server.load(UI_FONT);
+    loading_assets.add(&ui_assets.font);
+    ui_assets.panel = materials.add(Color::rgba(0.0, 0.0, 0.0, 0.6).into());
+    commands.spawn_bundle(UiCameraBundle::default());
+}
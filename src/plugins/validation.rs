@@ -0,0 +1,131 @@
+use std::fmt;
+
+use bevy::{asset::HandleId, prelude::*};
+
+use super::items::ItemAssets;
+
+// アセットの問題 1 件. ファイル, フィールド, 理由を持つ
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssetIssue {
+    pub file: String,
+    pub field: Option<String>,
+    pub reason: String,
+}
+
+impl AssetIssue {
+    pub fn new(file: &str, field: Option<&str>, reason: impl Into<String>) -> Self {
+        AssetIssue {
+            file: file.to_string(),
+            field: field.map(|field| field.to_string()),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for AssetIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{}: `{}`: {}", self.file, field, self.reason),
+            None => write!(f, "{}: {}", self.file, self.reason),
+        }
+    }
+}
+
+pub fn report(issues: &[AssetIssue]) {
+    for issue in issues {
+        error!("{}", issue);
+    }
+}
+
+// 1 つの .item の中身だけで分かる問題. テクスチャの有無は呼び出し側が判定する
+pub fn validate_item(
+    file: &str,
+    item: &ItemAssets,
+    texture_exists: impl Fn(&str) -> bool,
+) -> Vec<AssetIssue> {
+    let mut issues = Vec::new();
+    if item.id.is_empty() {
+        issues.push(AssetIssue::new(file, Some("id"), "must not be empty"));
+    }
+    if item.texture.is_empty() {
+        issues.push(AssetIssue::new(file, Some("texture"), "must not be empty"));
+    } else if !texture_exists(&item.texture) {
+        issues.push(AssetIssue::new(
+            file,
+            Some("texture"),
+            format!("texture `{}` not found", item.texture),
+        ));
+    }
+    for frame in item.frames.iter() {
+        if frame.is_empty() {
+            issues.push(AssetIssue::new(
+                file,
+                Some("frames"),
+                "must not contain an empty path",
+            ));
+        } else if !texture_exists(frame) {
+            issues.push(AssetIssue::new(
+                file,
+                Some("frames"),
+                format!("texture `{}` not found", frame),
+            ));
+        }
+    }
+    if item.frames.len() > 1 && item.frame_duration_ms == 0 {
+        issues.push(AssetIssue::new(
+            file,
+            Some("frame_duration_ms"),
+            "must be > 0 for an animated item",
+        ));
+    }
+    let (anchor_x, anchor_y) = item.anchor;
+    if !(0.0..=1.0).contains(&anchor_x) || !(0.0..=1.0).contains(&anchor_y) {
+        issues.push(AssetIssue::new(
+            file,
+            Some("anchor"),
+            format!("must be in (0.0, 0.0)..=(1.0, 1.0) (got {:?})", item.anchor),
+        ));
+    }
+    if let Some(size) = item.size {
+        if size.0 <= 0.0 || size.1 <= 0.0 {
+            issues.push(AssetIssue::new(
+                file,
+                Some("size"),
+                format!("must be > 0 (got {:?})", size),
+            ));
+        }
+    }
+    if !item.edible && item.recovery_amount > 0 {
+        issues.push(AssetIssue::new(
+            file,
+            Some("recovery_amount"),
+            "set on an item that is not edible",
+        ));
+    }
+    issues
+}
+
+// 同じ id を持つ定義が既にあれば問題として返す
+pub fn check_duplicate_id(
+    file: &str,
+    item: &ItemAssets,
+    first_file: Option<&str>,
+) -> Option<AssetIssue> {
+    first_file.map(|first_file| {
+        AssetIssue::new(
+            file,
+            Some("id"),
+            format!(
+                "duplicate id `{}` (already defined in {})",
+                item.id, first_file
+            ),
+        )
+    })
+}
+
+pub fn asset_file<H: Into<HandleId>>(asset_server: &AssetServer, handle: H) -> String {
+    asset_server
+        .get_handle_path(handle)
+        .map(|path| path.path().display().to_string())
+        .unwrap_or_else(|| "<unknown>".to_string())
+}
@@ -1,19 +1,42 @@
-use bevy::{prelude::*, render::camera::Camera};
+use bevy::{
+    app::{Events, ManualEventReader},
+    prelude::*,
+};
+use rand::{thread_rng, Rng};
 use std::collections::HashMap;
 
 use crate::agents::player::PlayerState;
 
-use super::items::{Item, Owner};
+use super::{
+    clock::{GameClock, SIMULATION},
+    config::GameConfig,
+    depth::Depth,
+    items::{Item, ItemOffset, Owner},
+};
 
-pub const YZ_PROJECTION_RATIO: f32 = -1.0;
-pub const Z_OFFSET: f32 = 500.0;
+pub const TILE_SIZE: f32 = 32.0;
 
-#[derive(Clone, Default, PartialEq, Eq, Hash, Copy)]
+#[derive(Clone, Default, PartialEq, Eq, Hash, Copy, Debug)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
 }
 
+impl Position {
+    // z は depth で決める
+    pub fn to_world(&self) -> Vec2 {
+        Vec2::new(self.x as f32 * TILE_SIZE, self.y as f32 * TILE_SIZE)
+    }
+
+    // セルの中心が to_world の xy なので四捨五入
+    pub fn from_world(world: Vec2) -> Position {
+        Position {
+            x: (world.x / TILE_SIZE).round() as i32,
+            y: (world.y / TILE_SIZE).round() as i32,
+        }
+    }
+}
+
 #[derive(Clone, Default, PartialEq, Copy)]
 pub struct TextureOffset {
     pub x: f32,
@@ -32,22 +55,62 @@ pub struct GameWorld {
     pub item_map: HashMap<Position, Entity>,
 }
 
-pub struct AddItemToWorldEvent(pub Entity, pub Position);
-pub struct RemoveItemFromWorldEvent(pub Entity, pub Position);
+// Resource
+// マップ生成の乱数の種. リプレイでは記録したものを使う
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WorldSeed(pub u64);
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        WorldSeed(thread_rng().gen())
+    }
+}
+
+// 同じセルへの追加と削除は入れ替わると結果が変わるので 1 つのイベントにして送った順に反映する
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorldItemEvent {
+    Add(Entity, Position),
+    // そのセルに居るのが別のアイテムなら何もしない
+    Remove(Entity, Position),
+}
+
+// Resource
+// SIMULATION と PostUpdate の apply_item_events が共有する読み位置
+#[derive(Default)]
+struct WorldItemEventCursor(ManualEventReader<WorldItemEvent>);
 
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_event::<AddItemToWorldEvent>()
-            .add_event::<RemoveItemFromWorldEvent>()
+        app.init_resource::<WorldSeed>()
+            .add_event::<WorldItemEvent>()
+            .init_resource::<WorldItemEventCursor>()
             .add_startup_system(setup.system())
-            .add_system(add_item.system())
-            .add_system(remove_item.system())
+            // 置いたり戻したりした結果を同じ tick の入力が見られるよう SIMULATION でも反映する.
+            // PostUpdate の方は Update や tick の無いフレームで送られたイベント用.
+            // 読み位置を共有しているので各イベントは一度だけ反映される
+            .add_system_to_stage(
+                SIMULATION,
+                apply_item_events
+                    .system()
+                    .label("world_events")
+                    .after("end_action_process")
+                    .after("history")
+                    .before("input"),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                apply_item_events.system().label("world_events"),
+            )
             .add_system(world_position_transform.system())
             .add_system(world_position_transform_delta.system().label("position"))
-            .add_system(camera_transform.system().after("position"))
-            .add_system(item_position_transform.system().after("position"));
+            .add_system(
+                item_position_transform
+                    .system()
+                    .label("item_position")
+                    .after("position"),
+            );
     }
 }
 
@@ -55,130 +118,127 @@ fn setup(mut commands: Commands) {
     commands.spawn().insert(GameWorld::default());
 }
 
-fn add_item(
-    mut event_reader: EventReader<AddItemToWorldEvent>,
+fn apply_item_events(
+    mut cursor: ResMut<WorldItemEventCursor>,
+    events: Res<Events<WorldItemEvent>>,
     mut world_query: Query<(&mut GameWorld,)>,
 ) {
-    for ev in event_reader.iter() {
+    for ev in cursor.0.iter(&events) {
         for (mut world,) in world_query.iter_mut() {
-            world.item_map.insert(ev.1, ev.0);
-        }
-    }
-}
-
-fn remove_item(
-    mut event_reader: EventReader<RemoveItemFromWorldEvent>,
-    mut world_query: Query<(&mut GameWorld,)>,
-) {
-    for ev in event_reader.iter() {
-        for (mut world,) in world_query.iter_mut() {
-            world.item_map.remove(&ev.1);
+            match *ev {
+                WorldItemEvent::Add(entity, pos) => {
+                    world.item_map.insert(pos, entity);
+                }
+                WorldItemEvent::Remove(entity, pos) => {
+                    if world.item_map.get(&pos) == Some(&entity) {
+                        world.item_map.remove(&pos);
+                    }
+                }
+            }
         }
     }
 }
 
 fn world_position_transform(
-    mut query: Query<(&Position, &mut Transform), (Without<OldPosition>,)>,
+    mut query: Query<
+        (
+            &Position,
+            &mut Transform,
+            Option<&mut Depth>,
+            Option<&ItemOffset>,
+        ),
+        (Without<OldPosition>,),
+    >,
 ) {
-    query.iter_mut().for_each(|(pos, mut transform)| {
-        transform.translation = Vec3::new(
-            pos.x as f32 * 32.0,
-            pos.y as f32 * 32.0,
-            pos.y as f32 * YZ_PROJECTION_RATIO + Z_OFFSET,
-        );
-    });
+    query
+        .iter_mut()
+        .for_each(|(pos, mut transform, depth, item_offset)| {
+            let mut world = pos.to_world();
+            if let Some(item_offset) = item_offset {
+                world += item_offset.offset;
+            }
+            transform.translation.x = world.x;
+            transform.translation.y = world.y;
+            if let Some(mut depth) = depth {
+                depth.row = pos.y as f32;
+                depth.bias = item_offset.map_or(0.0, |item_offset| item_offset.z_bias);
+            }
+        });
 }
 
 fn world_position_transform_delta(
+    clock: Res<GameClock>,
     mut query: Query<(
         &Position,
         &OldPosition,
         &mut Transform,
         &Timer,
         &TextureOffset,
+        Option<&mut Depth>,
     )>,
 ) {
     query
         .iter_mut()
-        .for_each(|(pos, old_pos, mut transform, timer, offset)| {
-            if f32::is_nan(timer.percent()) {
-                transform.translation = Vec3::new(
-                    pos.x as f32 * 32.0 + offset.x,
-                    pos.y as f32 * 32.0 + offset.y,
-                    pos.y as f32 * YZ_PROJECTION_RATIO + Z_OFFSET,
-                );
-            } else {
-                transform.translation = Vec3::new(
-                    (old_pos.x as f64 + timer.percent() as f64 * (pos.x - old_pos.x) as f64) as f32
-                        * 32.0
-                        + offset.x,
-                    (old_pos.y as f64 + timer.percent() as f64 * (pos.y - old_pos.y) as f64) as f32
-                        * 32.0
-                        + offset.y,
-                    (old_pos.y as f64 + timer.percent() as f64 * (pos.y - old_pos.y) as f64) as f32
-                        * YZ_PROJECTION_RATIO
-                        + Z_OFFSET,
-                );
+        .for_each(|(pos, old_pos, mut transform, timer, offset, depth)| {
+            // timer は tick ごとにしか進まないので描画ではその間を補う
+            let percent = clock.smooth_percent(timer) as f64;
+            let (x, y) = (
+                (old_pos.x as f64 + percent * (pos.x - old_pos.x) as f64) as f32,
+                (old_pos.y as f64 + percent * (pos.y - old_pos.y) as f64) as f32,
+            );
+            transform.translation.x = x * 32.0 + offset.x;
+            transform.translation.y = y * 32.0 + offset.y;
+            if let Some(mut depth) = depth {
+                depth.row = y;
             }
         });
 }
 
-fn camera_transform(
-    mut query: QuerySet<(
-        Query<(&Transform,), (With<PlayerState>,)>,
-        Query<(&mut Transform,), (With<Camera>,)>,
-    )>,
-) {
-    let mut vec = Default::default();
-    query.q0_mut().iter_mut().for_each(|(transform,)| {
-        vec = transform.translation;
-    });
-    query.q1_mut().iter_mut().for_each(|(mut transform,)| {
-        transform.translation = Vec3::new(vec.x, vec.y, 1000.0 - 0.1);
-    });
-}
-
-const OFFSET_X: f32 = -12.0;
-const OFFSET_Y: f32 = -6.0;
-
-const OFFSET_X_DROP: f32 = 0.0;
-const OFFSET_Y_DROP: f32 = -28.0;
-
 fn item_position_transform(
+    clock: Res<GameClock>,
+    config: Res<GameConfig>,
     mut query: QuerySet<(
-        Query<(Entity, &mut Transform), (With<Item>,)>,
-        Query<(Entity, &Owner), (With<Item>,)>,
-        Query<(&PlayerState, &Transform, &Timer)>,
+        Query<(Entity, &mut Transform, &mut Depth), (With<Item>,)>,
+        Query<(Entity, &Owner, &ItemOffset), (With<Item>,)>,
+        Query<(&PlayerState, &Transform, &Timer, &Depth)>,
     )>,
 ) {
-    let mut map: HashMap<Entity, (f32, f32, f32)> = HashMap::new();
-    query.q1().iter().for_each(|(item_entity, owner)| {
-        if let Result::Ok((state, parent_transform, timer)) = query.q2().get(owner.0) {
-            let pick_move = || {
-                (
-                    parent_transform.translation.x
-                        + OFFSET_X_DROP
-                        + f32::max(timer.percent() * 1.8 - 0.8, 0.0) * (OFFSET_X - OFFSET_X_DROP),
-                    parent_transform.translation.y
-                        + OFFSET_Y_DROP
-                        + f32::max(timer.percent() * 1.8 - 0.8, 0.0) * (OFFSET_Y - OFFSET_Y_DROP),
-                    parent_transform.translation.z,
-                )
-            };
-            let drop_move = || {
-                (
-                    parent_transform.translation.x
-                        + OFFSET_X
-                        + f32::min(timer.percent() * 1.5, 1.0) * (OFFSET_X_DROP - OFFSET_X),
-                    parent_transform.translation.y
-                        + OFFSET_Y
-                        + f32::min(timer.percent() * 1.5, 1.0) * (OFFSET_Y_DROP - OFFSET_Y),
-                    parent_transform.translation.z,
-                )
-            };
-            map.insert(
-                item_entity,
-                match *state {
+    let (offset_x_drop, offset_y_drop) = config.items.drop_offset;
+    // (x, y, 持ち主の行, z_bias)
+    let mut map: HashMap<Entity, (f32, f32, f32, f32)> = HashMap::new();
+    query
+        .q1()
+        .iter()
+        .for_each(|(item_entity, owner, item_offset)| {
+            // 定義に carry_offset があればそちらを使う
+            let (offset_x, offset_y) = item_offset
+                .carry_offset
+                .map_or(config.items.carry_offset, |offset| (offset.x, offset.y));
+            if let Result::Ok((state, parent_transform, timer, parent_depth)) =
+                query.q2().get(owner.0)
+            {
+                let percent = clock.smooth_percent(timer);
+                let pick_move = || {
+                    (
+                        parent_transform.translation.x
+                            + offset_x_drop
+                            + f32::max(percent * 1.8 - 0.8, 0.0) * (offset_x - offset_x_drop),
+                        parent_transform.translation.y
+                            + offset_y_drop
+                            + f32::max(percent * 1.8 - 0.8, 0.0) * (offset_y - offset_y_drop),
+                    )
+                };
+                let drop_move = || {
+                    (
+                        parent_transform.translation.x
+                            + offset_x
+                            + f32::min(percent * 1.5, 1.0) * (offset_x_drop - offset_x),
+                        parent_transform.translation.y
+                            + offset_y
+                            + f32::min(percent * 1.5, 1.0) * (offset_y_drop - offset_y),
+                    )
+                };
+                let (x, y) = match *state {
                     PlayerState::Pick(_) => pick_move(),
                     PlayerState::Drop => drop_move(),
                     PlayerState::PickAndDrop(item_entity_event) => {
@@ -190,20 +250,31 @@ fn item_position_transform(
                         }
                     }
                     _ => (
-                        parent_transform.translation.x + OFFSET_X,
-                        parent_transform.translation.y + OFFSET_Y,
-                        parent_transform.translation.z,
+                        parent_transform.translation.x + offset_x,
+                        parent_transform.translation.y + offset_y,
                     ),
-                },
-            );
-        }
-    });
+                };
+                map.insert(
+                    item_entity,
+                    (
+                        x + item_offset.offset.x,
+                        y + item_offset.offset.y,
+                        parent_depth.row,
+                        item_offset.z_bias,
+                    ),
+                );
+            }
+        });
     query
         .q0_mut()
         .iter_mut()
-        .for_each(|(item_entity, mut transform)| {
+        .for_each(|(item_entity, mut transform, mut depth)| {
             if let Some(vec) = map.get(&item_entity) {
-                transform.translation = Vec3::new(vec.0, vec.1, vec.2);
+                transform.translation.x = vec.0;
+                transform.translation.y = vec.1;
+                // z は depth で Carried として付ける
+                depth.row = vec.2;
+                depth.bias = vec.3;
             }
         });
 }
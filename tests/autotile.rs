@@ -0,0 +1,149 @@
+mod common;
+
+use bevy::{app::Events, prelude::*};
+use bevy_tilemap::prelude::*;
+use movable_tiles::plugins::{
+    autotile::Neighbourhood,
+    chunk::{TileChangedEvent, TileLayer},
+    items::TextureOverride,
+    textures::SpriteHandles,
+    world::Position,
+};
+
+use common::TestApp;
+
+fn wall_texture(app: &mut TestApp, x: i32, y: i32) -> Option<Handle<Texture>> {
+    let wall = app.item_at(Position { x, y }).expect("no wall");
+    app.app
+        .world
+        .get::<TextureOverride>(wall)
+        .map(|texture| texture.0.clone())
+}
+
+fn variant(app: &TestApp, mask: u8) -> Option<Handle<Texture>> {
+    let asset_server = app.app.world.get_resource::<AssetServer>().unwrap();
+    let texture = match mask {
+        0 => "textures/square-wall.png".to_string(),
+        mask => format!("textures/walls/wall-{:02}.png", mask),
+    };
+    Some(asset_server.get_handle(texture.as_str()))
+}
+
+#[test]
+fn corners_need_both_edges() {
+    let members = [(0, 1), (1, 1), (1, 0), (-1, -1)];
+    let mask = Neighbourhood::Eight.mask(Position { x: 0, y: 0 }, |pos| {
+        members.contains(&(pos.x, pos.y))
+    });
+    // 北, 北東, 東. 南西は南も西も無いので数えない
+    assert_eq!(mask, 0b0000_0111);
+    assert_eq!(Neighbourhood::Eight.normalize(0b0010_0000), 0);
+    assert_eq!(
+        Neighbourhood::Four.mask(Position { x: 0, y: 0 }, |_| true),
+        15
+    );
+}
+
+#[test]
+fn walls_connect_to_neighbours() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    app.console("spawn wall 5 5");
+    assert_eq!(wall_texture(&mut app, 5, 5), variant(&app, 0));
+
+    app.console("spawn wall 6 5");
+    app.console("spawn wall 5 6");
+    // 北 1 + 東 2
+    assert_eq!(wall_texture(&mut app, 5, 5), variant(&app, 3));
+    // 西 8
+    assert_eq!(wall_texture(&mut app, 6, 5), variant(&app, 8));
+    // 南 4
+    assert_eq!(wall_texture(&mut app, 5, 6), variant(&app, 4));
+
+    app.console("clear 6 5");
+    assert_eq!(wall_texture(&mut app, 5, 5), variant(&app, 1));
+}
+
+fn texture_index(app: &TestApp, texture: &str) -> usize {
+    let sprite_handles = app.app.world.get_resource::<SpriteHandles>().unwrap();
+    let atlases = app
+        .app
+        .world
+        .get_resource::<Assets<TextureAtlas>>()
+        .unwrap();
+    let asset_server = app.app.world.get_resource::<AssetServer>().unwrap();
+    atlases
+        .get(&sprite_handles.atlas)
+        .and_then(|atlas| atlas.get_texture_index(&asset_server.get_handle(texture)))
+        .expect("texture is not in the atlas")
+}
+
+fn ground_index(app: &mut TestApp, x: i32, y: i32) -> Option<usize> {
+    let mut query = app.app.world.query::<&mut Tilemap>();
+    let map = query
+        .iter_mut(&mut app.app.world)
+        .next()
+        .expect("no tilemap");
+    map.get_tile((x, y), TileLayer::Ground.sprite_order())
+        .map(|tile| tile.index)
+}
+
+#[test]
+fn sand_gets_edges_where_the_neighbour_is_not_sand() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    // TestApp は ChunkPlugin を使わないので, 描画しない tilemap を自分で置く
+    let sand = texture_index(&app, "textures/sand.png");
+    let edge = texture_index(&app, "textures/sand-edge.png");
+    let atlas = app
+        .app
+        .world
+        .get_resource::<SpriteHandles>()
+        .unwrap()
+        .atlas
+        .clone();
+    let mut map = Tilemap::builder()
+        .auto_chunk()
+        .topology(GridTopology::Square)
+        .dimensions(1, 1)
+        .chunk_dimensions(8, 8, 1)
+        .texture_dimensions(32, 32)
+        .z_layers(TileLayer::ALL.len())
+        .texture_atlas(atlas)
+        .finish()
+        .unwrap();
+    let cells = (0..3)
+        .flat_map(|y| (0..3).map(move |x| Position { x, y }))
+        .collect::<Vec<_>>();
+    map.insert_tiles(cells.iter().map(|pos| Tile {
+        point: (pos.x, pos.y),
+        sprite_order: TileLayer::Ground.sprite_order(),
+        sprite_index: sand,
+        tint: Color::WHITE,
+    }))
+    .unwrap();
+    app.app.world.spawn().insert(map);
+
+    let mut events = app
+        .app
+        .world
+        .get_resource_mut::<Events<TileChangedEvent>>()
+        .unwrap();
+    for pos in cells.iter() {
+        events.send(TileChangedEvent(*pos, TileLayer::Ground));
+    }
+    app.step();
+
+    // 縁に変えたタイルも砂として数えるので, 真ん中は四方とも砂のまま
+    assert_eq!(ground_index(&mut app, 1, 1), Some(sand));
+    for pos in cells.iter().filter(|pos| (pos.x, pos.y) != (1, 1)) {
+        assert_eq!(
+            ground_index(&mut app, pos.x, pos.y),
+            Some(edge),
+            "{:?}",
+            pos
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use movable_tiles::plugins::{
+    chunk::{chunk_ranges, tile_range},
+    config::WorldConfig,
+    world::Position,
+};
+
+fn world(chunks: (u32, u32), chunk_size: (u32, u32)) -> WorldConfig {
+    WorldConfig {
+        chunks,
+        chunk_size,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn tile_range_covers_every_chunk() {
+    // 偶数個のチャンクは原点のチャンク (0, 0) より左下に 1 つ多く並ぶ
+    let world = world((2, 3), (64, 16));
+    let ranges = chunk_ranges(&world);
+    assert_eq!(ranges.len(), 6);
+    let (min, max) = tile_range(&world);
+    assert_eq!(min, Position { x: -96, y: -24 });
+    assert_eq!(max, Position { x: 31, y: 23 });
+
+    let cells = ranges
+        .iter()
+        .map(|(min, max)| (max.x - min.x + 1) * (max.y - min.y + 1))
+        .sum::<i32>();
+    assert_eq!(cells, (max.x - min.x + 1) * (max.y - min.y + 1));
+    for (chunk_min, chunk_max) in ranges {
+        assert!(min.x <= chunk_min.x && chunk_max.x <= max.x);
+        assert!(min.y <= chunk_min.y && chunk_max.y <= max.y);
+    }
+}
+
+#[test]
+fn odd_chunk_counts_are_centred() {
+    let (min, max) = tile_range(&world((3, 1), (8, 8)));
+    assert_eq!(min, Position { x: -12, y: -4 });
+    assert_eq!(max, Position { x: 11, y: 3 });
+}
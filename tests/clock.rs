@@ -0,0 +1,78 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use movable_tiles::plugins::{
+    clock::{GameClock, MAX_TICKS_PER_FRAME},
+    world::Position,
+};
+
+use common::TestApp;
+
+fn ticks(app: &TestApp) -> u64 {
+    app.app.world.get_resource::<GameClock>().unwrap().tick()
+}
+
+fn set_frame(app: &mut TestApp, frame: Duration) {
+    app.app
+        .world
+        .get_resource_mut::<GameClock>()
+        .unwrap()
+        .manual_step = Some(frame);
+}
+
+// frame ずつ進めて duration だけゲーム内の時間を進める
+fn step_for(app: &mut TestApp, frame: Duration, duration: Duration) {
+    for _ in 0..(duration.as_nanos() / frame.as_nanos()) {
+        app.step();
+    }
+}
+
+// 拾ってから右へ歩き続けて止まる. 押している時間はどちらのフレームの幅でも割り切れるようにする
+fn play(frame: Duration) -> (u64, Position, Option<String>) {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+    set_frame(&mut app, frame);
+    let start = ticks(&app);
+
+    let ms = Duration::from_millis;
+    app.press(KeyCode::Z);
+    step_for(&mut app, frame, ms(40));
+    app.release(KeyCode::Z);
+    step_for(&mut app, frame, ms(520));
+    app.press(KeyCode::Right);
+    step_for(&mut app, frame, ms(440));
+    app.release(KeyCode::Right);
+    step_for(&mut app, frame, ms(400));
+
+    let hand = app.right_hand().and_then(|item| app.item_id(item));
+    (ticks(&app) - start, app.player_position(), hand)
+}
+
+#[test]
+fn frame_rate_does_not_change_the_result() {
+    let short = play(Duration::from_millis(10));
+    let long = play(Duration::from_millis(40));
+    assert_eq!(short, long);
+    // fixture の tick_rate は 50 なので 1400ms で 70 tick
+    assert_eq!(short.0, 70);
+    assert_eq!(short.2.as_deref(), Some("berry"));
+    assert_ne!(short.1, Position { x: 0, y: 0 });
+}
+
+#[test]
+fn long_frames_drop_extra_ticks() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+    let start = ticks(&app);
+
+    set_frame(&mut app, Duration::from_secs(1));
+    app.step();
+    assert_eq!(ticks(&app) - start, MAX_TICKS_PER_FRAME as u64);
+
+    // 捨てた分は後のフレームで取り戻さない
+    set_frame(&mut app, Duration::from_millis(20));
+    app.step();
+    assert_eq!(ticks(&app) - start, MAX_TICKS_PER_FRAME as u64 + 1);
+}
@@ -0,0 +1,251 @@
+// ウィンドウもレンダラーも使わずにゲームのプラグインを動かすテスト用のアプリ
+#![allow(dead_code)]
+
+use std::{thread, time::Duration};
+
+use bevy::{
+    app::Events,
+    asset::{AssetPlugin, AssetServerSettings},
+    input::{keyboard::KeyboardInput, ElementState, InputPlugin},
+    prelude::*,
+    render::texture::ImageTextureLoader,
+    text::FontLoader,
+    window::WindowId,
+};
+use movable_tiles::{
+    agents::player::{PlayerPlugin, PlayerState, RightHand},
+    plugins::{
+        aseprite::AsepritePlugin,
+        autotile::AutotilePlugin,
+        chunk::TileChangedEvent,
+        clock::{ClockPlugin, GameClock},
+        config::ConfigPlugin,
+        console::{Console, ConsolePlugin},
+        depth::DepthPlugin,
+        history::HistoryPlugin,
+        items::{self, Item, ItemDataMap, ItemsPlugin},
+        level::LoadLevelEvent,
+        loading::{AppState, LoadingPlugin},
+        textures::TexturePlugin,
+        ui::UiPlugin,
+        world::{GameWorld, Position, WorldItemEvent, WorldPlugin},
+    },
+};
+
+// 1 フレームで進めるゲーム内の時間. fixture の tick_rate (50) の 1 tick と揃えて
+// 1 フレーム 1 tick にする
+pub const FRAME: Duration = Duration::from_millis(20);
+
+// fixture の data.config の movement と同じ値
+pub const WALK: Duration = Duration::from_millis(200);
+pub const PICK: Duration = Duration::from_millis(500);
+
+// 読み込みを待つ最大フレーム数. 読み込みは別スレッドなので実時間で待つ
+const MAX_LOADING_FRAMES: usize = 2000;
+
+pub struct TestApp {
+    pub app: App,
+}
+
+impl TestApp {
+    // tests/fixtures/assets を読み込む. tilemap は描画が要るのでマップ生成は飛ばす
+    pub fn new() -> Self {
+        TestApp::with(|_| {})
+    }
+
+    // setup で追加のプラグインを足す
+    pub fn with(setup: impl FnOnce(&mut AppBuilder)) -> Self {
+        let mut builder = App::build();
+        builder
+            .insert_resource(AssetServerSettings {
+                asset_folder: format!("{}/tests/fixtures/assets", env!("CARGO_MANIFEST_DIR")),
+            })
+            .add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_plugin(InputPlugin)
+            // WindowPlugin の代わり. コンソールが文字入力を読む
+            .add_event::<ReceivedCharacter>()
+            .add_asset::<Texture>()
+            .init_asset_loader::<ImageTextureLoader>()
+            .add_asset::<TextureAtlas>()
+            .add_asset::<ColorMaterial>()
+            .add_asset::<Font>()
+            .init_asset_loader::<FontLoader>()
+            .add_plugin(ClockPlugin)
+            .add_plugin(LoadingPlugin)
+            .add_plugin(ConfigPlugin)
+            .add_plugin(AsepritePlugin)
+            .add_plugin(WorldPlugin)
+            .add_plugin(HistoryPlugin)
+            .add_plugin(DepthPlugin)
+            .add_plugin(TexturePlugin)
+            .add_plugin(ItemsPlugin)
+            // ChunkPlugin の代わり. オートタイルがタイルの変更を読む
+            .add_event::<TileChangedEvent>()
+            .add_plugin(AutotilePlugin)
+            .add_plugin(UiPlugin)
+            // EditorPlugin の代わり. コンソールの level が送る
+            .add_event::<LoadLevelEvent>()
+            .add_plugin(ConsolePlugin)
+            .add_plugin(PlayerPlugin)
+            .add_system_set(
+                SystemSet::on_update(AppState::Generating).with_system(skip_map.system()),
+            );
+        setup(&mut builder);
+        let mut app = std::mem::take(&mut builder.app);
+        app.world
+            .get_resource_mut::<GameClock>()
+            .unwrap()
+            .manual_step = Some(FRAME);
+        TestApp { app }
+    }
+
+    pub fn step(&mut self) {
+        self.app.update();
+    }
+
+    pub fn step_frames(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.step();
+        }
+    }
+
+    // duration 以上ゲーム内の時間が進むまでフレームを進める
+    pub fn step_for(&mut self, duration: Duration) {
+        let frames = (duration.as_nanos() + FRAME.as_nanos() - 1) / FRAME.as_nanos();
+        self.step_frames(frames as usize);
+    }
+
+    pub fn run_until_playing(&mut self) {
+        for _ in 0..MAX_LOADING_FRAMES {
+            if *self.state() == AppState::Playing {
+                // Generating で spawn したアイテムを反映させる
+                self.step();
+                return;
+            }
+            self.step();
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("fixture assets did not finish loading");
+    }
+
+    pub fn state(&self) -> &AppState {
+        self.app
+            .world
+            .get_resource::<State<AppState>>()
+            .unwrap()
+            .current()
+    }
+
+    pub fn press(&mut self, key: KeyCode) {
+        self.send_key(key, ElementState::Pressed);
+    }
+
+    pub fn release(&mut self, key: KeyCode) {
+        self.send_key(key, ElementState::Released);
+    }
+
+    // 1 フレームだけ押して離す
+    pub fn tap(&mut self, key: KeyCode) {
+        self.press(key);
+        self.step();
+        self.release(key);
+        self.step();
+    }
+
+    // コンソールの 1 行を実行する. コマンドは Update で走り, 置いたアイテムは同じフレームの
+    // PostUpdate で item_map に入ってオートタイルが付け直す
+    pub fn console(&mut self, line: &str) {
+        self.app
+            .world
+            .get_resource_mut::<Console>()
+            .unwrap()
+            .submit(line);
+        self.step_frames(2);
+    }
+
+    // 文字入力を送って 1 フレーム進める
+    pub fn type_text(&mut self, text: &str) {
+        let mut events = self
+            .app
+            .world
+            .get_resource_mut::<Events<ReceivedCharacter>>()
+            .unwrap();
+        for char in text.chars() {
+            events.send(ReceivedCharacter {
+                id: WindowId::primary(),
+                char,
+            });
+        }
+        self.step();
+    }
+
+    fn send_key(&mut self, key: KeyCode, state: ElementState) {
+        self.app
+            .world
+            .get_resource_mut::<Events<KeyboardInput>>()
+            .unwrap()
+            .send(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(key),
+                state,
+            });
+    }
+
+    pub fn player_position(&mut self) -> Position {
+        let mut query = self
+            .app
+            .world
+            .query_filtered::<&Position, With<PlayerState>>();
+        *query.iter(&self.app.world).next().expect("no player")
+    }
+
+    pub fn right_hand(&mut self) -> Option<Entity> {
+        let mut query = self.app.world.query::<&RightHand>();
+        query.iter(&self.app.world).next().expect("no player").0
+    }
+
+    pub fn item_at(&mut self, pos: Position) -> Option<Entity> {
+        let mut query = self.app.world.query::<&GameWorld>();
+        query
+            .iter(&self.app.world)
+            .find_map(|world| world.item_map.get(&pos).copied())
+    }
+
+    pub fn item_id(&self, entity: Entity) -> Option<String> {
+        self.app
+            .world
+            .get::<Item>(entity)
+            .map(|item| item.id().to_string())
+    }
+}
+
+impl Default for TestApp {
+    fn default() -> Self {
+        TestApp::new()
+    }
+}
+
+// マップを生成する代わりにテストで使うアイテムを置く
+fn skip_map(
+    mut commands: Commands,
+    mut state: ResMut<State<AppState>>,
+    mut item_events: EventWriter<WorldItemEvent>,
+    item_data: Res<ItemDataMap>,
+) {
+    for (item_id, x, y) in [
+        ("berry", 0, 0),
+        ("wall", 1, 0),
+        ("berry", 2, 0),
+        ("berry", 3, 0),
+    ] {
+        items::spawn_item(
+            &mut commands,
+            &mut item_events,
+            &item_data,
+            item_id,
+            Position { x, y },
+        );
+    }
+    let _ = state.set(AppState::Playing);
+}
@@ -0,0 +1,56 @@
+use movable_tiles::plugins::config::{FogConfig, GameConfigAsset};
+
+// セクションごとに 1 つずつ値を変えて, そのフィールドだけが変更として出るか
+#[test]
+fn diff_reports_every_field() {
+    let fields: &[(&str, fn(&mut GameConfigAsset))] = &[
+        ("damage", |c| c.damage += 1.0),
+        ("durability", |c| c.durability += 1.0),
+        ("min_level", |c| c.min_level += 1),
+        ("movement.speed", |c| c.movement.speed += 1),
+        ("movement.walk_duration_ms", |c| {
+            c.movement.walk_duration_ms += 1
+        }),
+        ("movement.pick_duration_ms", |c| {
+            c.movement.pick_duration_ms += 1
+        }),
+        ("items.carry_offset", |c| c.items.carry_offset.0 += 1.0),
+        ("items.drop_offset", |c| c.items.drop_offset.1 += 1.0),
+        ("world.chunks", |c| c.world.chunks.0 += 1),
+        ("world.chunk_size", |c| c.world.chunk_size.1 += 1),
+        ("world.player_y_offset", |c| c.world.player_y_offset += 1.0),
+        ("world.decorations", |c| {
+            c.world.decorations.push(Default::default())
+        }),
+        ("world.fog", |c| c.world.fog = Some(FogConfig::default())),
+        ("terrain.height_frequency", |c| {
+            c.terrain.height_frequency *= 2.0
+        }),
+        ("terrain.moisture_frequency", |c| {
+            c.terrain.moisture_frequency *= 2.0
+        }),
+        ("terrain.octaves", |c| c.terrain.octaves += 1),
+        ("terrain.spawn_clearance", |c| {
+            c.terrain.spawn_clearance += 1
+        }),
+        ("terrain.biomes", |c| c.terrain.biomes.clear()),
+        ("camera.follow_speed", |c| c.camera.follow_speed += 1.0),
+        ("camera.dead_zone", |c| c.camera.dead_zone.0 += 1.0),
+        ("camera.zoom", |c| c.camera.zoom += 1),
+        ("camera.min_zoom", |c| c.camera.min_zoom += 1),
+        ("camera.max_zoom", |c| c.camera.max_zoom += 1),
+        ("simulation.tick_rate", |c| c.simulation.tick_rate += 1),
+    ];
+    let old = GameConfigAsset::default();
+    assert!(old.diff(&old).is_empty());
+    for (field, change) in fields {
+        let mut new = old.clone();
+        change(&mut new);
+        let changed = old
+            .diff(&new)
+            .into_iter()
+            .map(|change| change.field)
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec![*field]);
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use bevy::prelude::*;
+use movable_tiles::plugins::{console::Console, world::Position};
+
+use common::TestApp;
+
+fn last_line(app: &TestApp) -> String {
+    let console = app.app.world.get_resource::<Console>().unwrap();
+    console.log().last().cloned().unwrap_or_default()
+}
+
+fn input(app: &TestApp) -> String {
+    let console = app.app.world.get_resource::<Console>().unwrap();
+    console.input().to_string()
+}
+
+#[test]
+fn spawn_give_and_clear() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    let pos = Position { x: 3, y: 4 };
+    app.console("spawn wall 3 4");
+    let wall = app.item_at(pos).expect("wall was not spawned");
+    assert_eq!(app.item_id(wall).as_deref(), Some("wall"));
+
+    app.console("spawn berry 3 4");
+    assert_eq!(last_line(&app), "(3, 4) is not empty");
+
+    app.console("clear 3 4");
+    assert_eq!(app.item_at(pos), None);
+
+    app.console("give berry");
+    let berry = app.right_hand().expect("nothing in hand");
+    assert_eq!(app.item_id(berry).as_deref(), Some("berry"));
+
+    app.console("tp 2 -1");
+    assert_eq!(app.player_position(), Position { x: 2, y: -1 });
+
+    app.console("teleport 0 0");
+    assert_eq!(last_line(&app), "unknown command `teleport` (try `help`)");
+}
+
+#[test]
+fn tab_completes_commands_and_item_ids() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+    app.tap(KeyCode::Grave);
+
+    // 1 つに決まらなければ候補を出して入力はそのまま
+    app.type_text("s");
+    app.tap(KeyCode::Tab);
+    assert_eq!(input(&app), "s");
+    assert_eq!(last_line(&app), "save  seed  spawn");
+
+    app.type_text("p");
+    app.tap(KeyCode::Tab);
+    assert_eq!(input(&app), "spawn ");
+
+    // 共通部分は文字単位で伸ばす (ぶ は 3 バイト)
+    app.type_text("ぶ");
+    app.tap(KeyCode::Tab);
+    assert_eq!(input(&app), "spawn ぶ");
+    assert_eq!(last_line(&app), "ぶどう  ぶんたん");
+
+    app.type_text("ど");
+    app.tap(KeyCode::Tab);
+    assert_eq!(input(&app), "spawn ぶどう ");
+}
+
+#[test]
+fn up_and_down_walk_through_history() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+    app.tap(KeyCode::Grave);
+
+    for line in ["seed", "help", "help"] {
+        app.type_text(line);
+        app.tap(KeyCode::Return);
+    }
+    // 続けて同じ行は 1 つにまとめる
+    app.tap(KeyCode::Up);
+    assert_eq!(input(&app), "help");
+    app.tap(KeyCode::Up);
+    assert_eq!(input(&app), "seed");
+    app.tap(KeyCode::Up);
+    assert_eq!(input(&app), "seed");
+
+    app.tap(KeyCode::Down);
+    assert_eq!(input(&app), "help");
+    app.tap(KeyCode::Down);
+    assert_eq!(input(&app), "");
+}
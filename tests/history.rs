@@ -0,0 +1,58 @@
+mod common;
+
+use bevy::prelude::*;
+use movable_tiles::plugins::{history::History, world::Position};
+
+use common::{TestApp, PICK, WALK};
+
+#[test]
+fn undo_and_redo_pick_and_walk() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    let origin = Position { x: 0, y: 0 };
+    let below = Position { x: 0, y: -1 };
+    let berry = app.item_at(origin).unwrap();
+    app.tap(KeyCode::Z);
+    app.step_for(PICK);
+    app.tap(KeyCode::Down);
+    app.step_for(WALK);
+    assert_eq!(app.player_position(), below);
+
+    // 歩いた分
+    app.tap(KeyCode::U);
+    assert_eq!(app.player_position(), origin);
+    assert_eq!(app.right_hand(), Some(berry));
+
+    // 拾った分
+    app.tap(KeyCode::U);
+    assert_eq!(app.right_hand(), None);
+    assert_eq!(app.item_at(origin), Some(berry));
+
+    app.tap(KeyCode::Y);
+    assert_eq!(app.right_hand(), Some(berry));
+    assert_eq!(app.item_at(origin), None);
+    app.tap(KeyCode::Y);
+    assert_eq!(app.player_position(), below);
+}
+
+#[test]
+fn undo_stops_when_the_cell_is_taken() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    let origin = Position { x: 0, y: 0 };
+    let berry = app.item_at(origin).unwrap();
+    app.tap(KeyCode::Z);
+    app.step_for(PICK);
+    assert_eq!(app.right_hand(), Some(berry));
+
+    // 拾った後のセルに別のアイテムを置くと, 拾った分は戻せない
+    app.console("spawn wall 0 0");
+    let wall = app.item_at(origin).unwrap();
+    app.tap(KeyCode::U);
+    assert_eq!(app.right_hand(), Some(berry));
+    assert_eq!(app.item_at(origin), Some(wall));
+    let history = app.app.world.get_resource::<History>().unwrap();
+    assert_eq!((history.undo_len(), history.redo_len()), (1, 0));
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use bevy::prelude::*;
+use movable_tiles::{
+    agents::player::Vitals,
+    plugins::hud::{HudBar, HudPlugin},
+};
+
+use common::TestApp;
+
+fn bar_width(app: &mut TestApp, bar: HudBar) -> Val {
+    let mut query = app.app.world.query::<(&HudBar, &Style)>();
+    query
+        .iter(&app.app.world)
+        .find(|(other, _)| **other == bar)
+        .map(|(_, style)| style.size.width)
+        .expect("no bar")
+}
+
+#[test]
+fn bars_follow_vitals() {
+    let mut app = TestApp::with(|app| {
+        app.add_plugin(HudPlugin);
+    });
+    app.run_until_playing();
+    app.step();
+    assert_eq!(bar_width(&mut app, HudBar::Health), Val::Percent(100.0));
+    assert_eq!(bar_width(&mut app, HudBar::Hunger), Val::Percent(100.0));
+
+    let mut query = app.app.world.query::<&mut Vitals>();
+    for mut vitals in query.iter_mut(&mut app.app.world) {
+        vitals.health = 50.0;
+        vitals.hunger = 25.0;
+    }
+    app.step();
+    assert_eq!(bar_width(&mut app, HudBar::Health), Val::Percent(50.0));
+    assert_eq!(bar_width(&mut app, HudBar::Hunger), Val::Percent(25.0));
+
+    // 0..=1 に収める
+    let mut query = app.app.world.query::<&mut Vitals>();
+    for mut vitals in query.iter_mut(&mut app.app.world) {
+        vitals.health = -10.0;
+        vitals.hunger = vitals.max_hunger * 2.0;
+    }
+    app.step();
+    assert_eq!(bar_width(&mut app, HudBar::Health), Val::Percent(0.0));
+    assert_eq!(bar_width(&mut app, HudBar::Hunger), Val::Percent(100.0));
+}
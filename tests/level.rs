@@ -0,0 +1,94 @@
+use movable_tiles::plugins::{chunk::TileLayer, level::LevelFile, world::Position};
+
+fn empty_level() -> LevelFile {
+    LevelFile::new(Position { x: -2, y: -1 }, Position { x: 1, y: 1 })
+}
+
+#[test]
+fn tiles_round_trip_per_layer() {
+    let mut level = empty_level();
+    assert_eq!(level.size, (4, 3));
+    assert_eq!(level.tiles.len(), 12);
+
+    let corner = Position { x: 1, y: 1 };
+    level.set_tile(corner, TileLayer::Ground, Some("textures/sand.png"));
+    level.set_tile(corner, TileLayer::Decoration, Some("textures/crack.png"));
+    // origin から x が先に増える
+    assert_eq!(level.tiles[11], Some(0));
+    assert_eq!(
+        level.tile(corner, TileLayer::Ground),
+        Some("textures/sand.png")
+    );
+    assert_eq!(
+        level.tile(corner, TileLayer::Decoration),
+        Some("textures/crack.png")
+    );
+
+    // 同じ画像は palette で使い回す
+    let origin = Position { x: -2, y: -1 };
+    level.set_tile(origin, TileLayer::Ground, Some("textures/sand.png"));
+    assert_eq!(level.tiles[0], Some(0));
+    assert_eq!(level.palette.len(), 2);
+
+    level.set_tile(corner, TileLayer::Ground, None);
+    assert_eq!(level.tile(corner, TileLayer::Ground), None);
+}
+
+#[test]
+fn cells_outside_and_overlay_are_ignored() {
+    let mut level = empty_level();
+    for pos in [
+        Position { x: 2, y: 0 },
+        Position { x: -3, y: 0 },
+        Position { x: 0, y: 2 },
+    ] {
+        level.set_tile(pos, TileLayer::Ground, Some("textures/sand.png"));
+        assert_eq!(level.tile(pos, TileLayer::Ground), None);
+    }
+    let pos = Position { x: 0, y: 0 };
+    level.set_tile(pos, TileLayer::Overlay, Some("textures/fog.png"));
+    assert_eq!(level.tile(pos, TileLayer::Overlay), None);
+    assert_eq!(level, empty_level());
+}
+
+#[test]
+fn files_without_decorations_still_load() {
+    let mut level: LevelFile = ron::de::from_str(
+        r#"(origin: (0, 0), size: (2, 1), palette: ["textures/sand.png"], tiles: [Some(0), None], items: [((1, 0), "wall")])"#,
+    )
+    .unwrap();
+    let pos = Position { x: 1, y: 0 };
+    assert_eq!(level.tile(pos, TileLayer::Decoration), None);
+    level.set_tile(pos, TileLayer::Decoration, Some("textures/crack.png"));
+    assert_eq!(
+        level.tile(pos, TileLayer::Decoration),
+        Some("textures/crack.png")
+    );
+
+    let path = std::env::temp_dir().join(format!("movable-tiles-{}.level", std::process::id()));
+    level.save(&path).unwrap();
+    let loaded = LevelFile::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, level);
+}
+
+#[test]
+fn tiles_past_the_palette_are_rejected() {
+    let mut level = empty_level();
+    level.tiles[0] = Some(3);
+    assert_eq!(
+        level.tile(Position { x: -2, y: -1 }, TileLayer::Ground),
+        None
+    );
+
+    let path =
+        std::env::temp_dir().join(format!("movable-tiles-{}-broken.level", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"(origin: (0, 0), size: (2, 1), palette: ["textures/sand.png"], tiles: [Some(0), None], decorations: [None, Some(1)], items: [])"#,
+    )
+    .unwrap();
+    let loaded = LevelFile::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(loaded.is_err());
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use bevy::prelude::*;
+use movable_tiles::plugins::world::Position;
+
+use common::{TestApp, PICK, WALK};
+
+#[test]
+fn walk_moves_one_cell() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    app.tap(KeyCode::Right);
+    app.step_for(WALK);
+    assert_eq!(app.player_position(), Position { x: 1, y: 0 });
+
+    app.tap(KeyCode::Up);
+    app.step_for(WALK);
+    assert_eq!(app.player_position(), Position { x: 1, y: 1 });
+}
+
+#[test]
+fn pick_up_item_under_player() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    let origin = Position { x: 0, y: 0 };
+    let berry = app.item_at(origin).expect("no item at the origin");
+    assert_eq!(app.item_id(berry).as_deref(), Some("berry"));
+
+    app.tap(KeyCode::Z);
+    app.step_for(PICK);
+    assert_eq!(app.right_hand(), Some(berry));
+    assert_eq!(app.item_at(origin), None);
+}
+
+#[test]
+fn drop_item_on_empty_cell() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    let berry = app.item_at(Position { x: 0, y: 0 }).unwrap();
+    app.tap(KeyCode::Z);
+    app.step_for(PICK);
+
+    app.tap(KeyCode::Down);
+    app.step_for(WALK);
+    let below = Position { x: 0, y: -1 };
+    assert_eq!(app.player_position(), below);
+    assert_eq!(app.item_at(below), None);
+
+    app.tap(KeyCode::Z);
+    app.step_for(PICK);
+    assert_eq!(app.right_hand(), None);
+    assert_eq!(app.item_at(below), Some(berry));
+}
+
+#[test]
+fn pick_and_drop_swaps_items() {
+    let mut app = TestApp::new();
+    app.run_until_playing();
+
+    let berry = app.item_at(Position { x: 0, y: 0 }).unwrap();
+    app.tap(KeyCode::Z);
+    app.step_for(PICK);
+
+    app.tap(KeyCode::Right);
+    app.step_for(WALK);
+    let wall_pos = Position { x: 1, y: 0 };
+    let wall = app.item_at(wall_pos).unwrap();
+    assert_eq!(app.item_id(wall).as_deref(), Some("wall"));
+
+    app.tap(KeyCode::Z);
+    app.step_for(PICK);
+    assert_eq!(app.right_hand(), Some(wall));
+    assert_eq!(app.item_at(wall_pos), Some(berry));
+}
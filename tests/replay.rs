@@ -0,0 +1,117 @@
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    app::{AppExit, Events},
+    prelude::*,
+};
+use movable_tiles::plugins::{
+    replay::{ReplayFile, ReplayMode, ReplayPlayer, ReplayPlugin},
+    world::Position,
+};
+
+use common::{TestApp, PICK, WALK};
+
+const MAX_REPLAY_FRAMES: usize = 1000;
+
+fn replay_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "movable-tiles-{}-{}.replay",
+        std::process::id(),
+        name
+    ))
+}
+
+// 拾って右に歩くところを記録する
+fn record(path: &Path) {
+    let mut recording = TestApp::with(|app| {
+        app.add_plugin(ReplayPlugin {
+            mode: ReplayMode::Record(path.to_path_buf()),
+        });
+    });
+    recording.run_until_playing();
+    recording.tap(KeyCode::Z);
+    recording.step_for(PICK);
+    recording.tap(KeyCode::Right);
+    recording.step_for(WALK);
+    recording
+        .app
+        .world
+        .get_resource_mut::<Events<AppExit>>()
+        .unwrap()
+        .send(AppExit);
+    recording.step();
+}
+
+// 結果が出るまで再生する
+fn replay(path: &Path) -> Option<bool> {
+    let mut replaying = TestApp::with(|app| {
+        app.add_plugin(ReplayPlugin {
+            mode: ReplayMode::Replay(path.to_path_buf()),
+        });
+    });
+    replaying.run_until_playing();
+    for _ in 0..MAX_REPLAY_FRAMES {
+        replaying.step();
+        let result = replaying
+            .app
+            .world
+            .get_resource::<ReplayPlayer>()
+            .unwrap()
+            .result();
+        if result.is_some() {
+            return result;
+        }
+    }
+    None
+}
+
+#[test]
+fn replay_reaches_recorded_world_hash() {
+    let path = replay_path("hash");
+    record(&path);
+
+    let file = ReplayFile::load(&path).expect("replay was not saved");
+    assert!(file.actions.iter().any(|action| action.interact));
+    assert!(file.actions.iter().any(|action| action.walk == (1, 0)));
+    assert_eq!(file.config.map(|config| config.tick_rate), Some(50));
+
+    let result = replay(&path);
+    let _ = fs::remove_file(&path);
+    assert_eq!(result, Some(true));
+}
+
+#[test]
+fn replay_refuses_a_different_config() {
+    let path = replay_path("config");
+    record(&path);
+
+    let mut file = ReplayFile::load(&path).expect("replay was not saved");
+    if let Some(config) = file.config.as_mut() {
+        config.movement.walk_duration_ms *= 2;
+    }
+    file.save(&path).unwrap();
+    let result = replay(&path);
+    let _ = fs::remove_file(&path);
+    assert_eq!(result, Some(false));
+}
+
+#[test]
+fn console_can_not_change_the_world_while_recording() {
+    let path = replay_path("console");
+    let mut recording = TestApp::with(|app| {
+        app.add_plugin(ReplayPlugin {
+            mode: ReplayMode::Record(path.clone()),
+        });
+    });
+    recording.run_until_playing();
+    recording.console("spawn wall 3 3");
+    recording.console("tp 2 2");
+    let _ = fs::remove_file(&path);
+    assert_eq!(recording.item_at(Position { x: 3, y: 3 }), None);
+    assert_eq!(recording.player_position(), Position { x: 0, y: 0 });
+}
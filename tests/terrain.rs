@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use movable_tiles::plugins::{
+    chunk::chunk_ranges,
+    config::{BiomeConfig, SpawnRule, TerrainConfig, WorldConfig},
+    terrain::TerrainGenerator,
+    world::Position,
+};
+
+const SEED: u64 = 0x1234_5678_9abc_def0;
+
+fn chunk(x: i32, y: i32) -> (Position, Position) {
+    let min = Position {
+        x: x * 16 - 8,
+        y: y * 16 - 8,
+    };
+    (
+        min,
+        Position {
+            x: min.x + 15,
+            y: min.y + 15,
+        },
+    )
+}
+
+fn biome(name: &str, height: (f64, f64), items: Vec<SpawnRule>) -> BiomeConfig {
+    BiomeConfig {
+        name: name.to_string(),
+        height,
+        moisture: (0.0, 1.0),
+        tiles: vec![format!("textures/{}.png", name)],
+        items,
+    }
+}
+
+fn rock_and_sand() -> TerrainConfig {
+    TerrainConfig {
+        spawn_clearance: 2,
+        biomes: vec![
+            biome(
+                "rock",
+                (0.5, 1.0),
+                vec![SpawnRule {
+                    item: "wall".to_string(),
+                    chance: 1.0,
+                }],
+            ),
+            biome("sand", (0.0, 1.0), Vec::new()),
+        ],
+        ..Default::default()
+    }
+}
+
+// 画像が何種類もあり, アイテムの確率が 1 未満なので中身がチャンクの乱数で変わる
+fn mixed() -> TerrainConfig {
+    let rule = |item: &str, chance: f32| SpawnRule {
+        item: item.to_string(),
+        chance,
+    };
+    let tiles = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| format!("textures/{}.png", name))
+            .collect::<Vec<_>>()
+    };
+    TerrainConfig {
+        spawn_clearance: 2,
+        biomes: vec![
+            BiomeConfig {
+                tiles: tiles(&["rock", "rock-cracked", "gravel"]),
+                ..biome(
+                    "rock",
+                    (0.5, 1.0),
+                    vec![rule("wall", 0.3), rule("berry", 0.2)],
+                )
+            },
+            BiomeConfig {
+                tiles: tiles(&["sand", "sand-dark"]),
+                ..biome("sand", (0.0, 1.0), vec![rule("berry", 0.1)])
+            },
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn chunks_do_not_depend_on_each_other() {
+    let config = mixed();
+    let world = WorldConfig {
+        chunks: (3, 2),
+        chunk_size: (16, 16),
+        ..Default::default()
+    };
+    let ranges = chunk_ranges(&world);
+
+    // build_world と同じくマップ全体をチャンクごとに生成する. 順番は逆にしておく
+    let generator = TerrainGenerator::new(SEED, &config);
+    let mut tiles = HashMap::new();
+    let mut items = HashMap::new();
+    for (min, max) in ranges.iter().rev() {
+        let content = generator.generate_chunk(*min, *max);
+        tiles.extend(content.tiles);
+        items.extend(content.items);
+    }
+    assert_eq!(tiles.len(), 48 * 32);
+    let textures = tiles.values().collect::<HashSet<_>>();
+    assert!(textures.len() > 2, "{:?}", textures);
+    assert!(!items.is_empty());
+
+    for (min, max) in ranges {
+        let alone = TerrainGenerator::new(SEED, &config).generate_chunk(min, max);
+        assert_eq!(alone.tiles.len(), 16 * 16);
+        for (pos, texture) in alone.tiles.iter() {
+            assert_eq!(tiles.get(pos), Some(texture), "{:?}", pos);
+        }
+        let inside = items
+            .iter()
+            .filter(|(pos, _)| min.x <= pos.x && pos.x <= max.x && min.y <= pos.y && pos.y <= max.y)
+            .map(|(pos, item)| (*pos, item.clone()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            alone.items.into_iter().collect::<HashMap<_, _>>(),
+            inside,
+            "{:?}",
+            min
+        );
+    }
+}
+
+#[test]
+fn chunks_are_seeded_by_position() {
+    let config = TerrainConfig::default();
+    let generator = TerrainGenerator::new(SEED, &config);
+    let (min, max) = chunk(1, -1);
+    let alone = generator.generate_chunk(min, max);
+    assert_eq!(alone.tiles.len(), 16 * 16);
+
+    let generator = TerrainGenerator::new(SEED, &config);
+    let (other_min, other_max) = chunk(0, 0);
+    generator.generate_chunk(other_min, other_max);
+    assert_eq!(generator.generate_chunk(min, max), alone);
+
+    let generator = TerrainGenerator::new(SEED + 1, &config);
+    assert_ne!(generator.generate_chunk(min, max), alone);
+}
+
+#[test]
+fn biomes_follow_height_and_spawn_rules() {
+    let config = rock_and_sand();
+    let generator = TerrainGenerator::new(SEED, &config);
+    let (min, max) = chunk(0, 0);
+    let content = generator.generate_chunk(min, max);
+
+    for (pos, texture) in content.tiles.iter() {
+        let (height, _) = generator.sample(*pos);
+        let expected = if height >= 0.5 { "rock" } else { "sand" };
+        assert_eq!(*texture, format!("textures/{}.png", expected), "{:?}", pos);
+
+        let cleared = pos.x.abs() <= 2 && pos.y.abs() <= 2;
+        let has_wall = content.items.contains(&(*pos, "wall".to_string()));
+        assert_eq!(has_wall, expected == "rock" && !cleared, "{:?}", pos);
+    }
+}